@@ -1,69 +1,461 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::io::{self, BufRead};
+use std::str::FromStr;
 
 pub fn part_one() {
-    let seat_locations = read_input(io::stdin().lock());
-    let answer = get_highest_seat_id(&seat_locations);
-
-    println!("{}", answer);
+    part_one_with_plane(&SeatPlane::default())
 }
 
 pub fn part_two() {
-    let seat_locations = read_input(io::stdin().lock());
-    let answer = get_open_seat_id(&seat_locations);
+    part_two_with_plane(&SeatPlane::default())
+}
+
+pub fn part_one_with_plane(plane: &SeatPlane) {
+    match read_input(io::stdin().lock(), plane) {
+        Ok(seat_locations) => match get_highest_seat_id(&seat_locations, plane) {
+            Some(id) => println!("{}", id),
+            None => println!("No boarding passes were given"),
+        },
+        Err(msg) => println!("{}", msg),
+    }
+}
+
+pub fn part_two_with_plane(plane: &SeatPlane) {
+    match read_input(io::stdin().lock(), plane) {
+        Ok(seat_locations) => match get_open_seat_id(&seat_locations, plane) {
+            Some(id) => println!("{}", id),
+            None => println!("No open seat with filled seats on both sides"),
+        },
+        Err(msg) => println!("{}", msg),
+    }
+}
+
+/// Prints a map of the whole plane followed by the empty seats, duplicate boarding passes, and
+/// missing runs of seats at the front and back of the plane.
+pub fn seat_map(plane: &SeatPlane) {
+    match read_input(io::stdin().lock(), plane) {
+        Ok(seat_locations) => {
+            let occupancy = Occupancy::new(&seat_locations, plane);
+            print!("{}", occupancy.render());
+            print!("{}", occupancy.report());
+        }
+        Err(msg) => println!("{}", msg),
+    }
+}
+
+/// Assigns seats to each of the given groups of passengers around the seats already filled by the
+/// boarding passes on standard input, and prints the boarding passes for each group.
+pub fn allocate(plane: &SeatPlane, group_sizes: &[u32], rules: Vec<BlockingRule>) {
+    let seat_locations = match read_input(io::stdin().lock(), plane) {
+        Ok(seat_locations) => seat_locations,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
 
-    println!("{}", answer);
+    let mut allocator = SeatAllocator::new(&seat_locations, plane, rules);
+    for (i, group_size) in group_sizes.iter().enumerate() {
+        match allocator.allocate(*group_size) {
+            Ok(seats) => println!(
+                "Group {} ({} seats): {}",
+                i + 1,
+                group_size,
+                seats
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+            Err(msg) => println!("Group {} ({} seats): {}", i + 1, group_size, msg),
+        }
+    }
 }
 
+/// Prints the boarding pass for each seat given on standard input, either as a seat ID or as a
+/// row and column separated by a comma.
+pub fn encode(plane: &SeatPlane) {
+    for (i, line) in io::stdin().lock().lines().enumerate() {
+        let line = line.unwrap();
+
+        let location = match parse_seat(&line) {
+            Some((id, None)) => SeatLocation::from_id(id, plane),
+            Some((row, Some(column))) => {
+                SeatLocation::from_position(&Position { row, column }, plane)
+            }
+            None => Err(format!("Invalid seat: {}", line)),
+        };
+
+        match location {
+            Ok(location) => println!("{}", location),
+            Err(msg) => println!("Line {}: {}", i + 1, msg),
+        }
+    }
+}
+
+/// Parses either a seat ID or a row and column separated by a comma, returning the ID or the row
+/// along with the column if one was given.
+fn parse_seat(seat_str: &str) -> Option<(u32, Option<u32>)> {
+    let parts: Vec<&str> = seat_str.trim().split(',').collect();
+    match parts.as_slice() {
+        [id] => id.trim().parse().ok().map(|id| (id, None)),
+        [row, column] => match (row.trim().parse(), column.trim().parse()) {
+            (Ok(row), Ok(column)) => Some((row, Some(column))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Describes the size of a plane and the letters used on its boarding passes. Each boarding pass
+/// gives the row bits followed by the column bits, most significant bit first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SeatPlane {
+    row_bits: u32,
+    column_bits: u32,
+    /// Letters that select the lower and upper halves of the remaining rows.
+    row_letters: (char, char),
+    /// Letters that select the lower and upper halves of the remaining columns.
+    column_letters: (char, char),
+}
+
+impl SeatPlane {
+    /// Creates a plane with the given number of row and column bits, where the letters are given as
+    /// the lower row, upper row, lower column, and upper column letters (ex. "FBLR").
+    pub fn new(row_bits: u32, column_bits: u32, letters: &str) -> Result<SeatPlane, String> {
+        if !matches!(row_bits.checked_add(column_bits), Some(bits) if bits <= 31) {
+            return Err(format!(
+                "Plane has too many seats: {} row bits and {} column bits",
+                row_bits, column_bits
+            ));
+        }
+
+        let letters: Vec<char> = letters.chars().collect();
+        if letters.len() != 4 {
+            return Err(format!(
+                "Expected four boarding pass letters, found: {}",
+                letters.iter().collect::<String>()
+            ));
+        }
+        if letters.iter().collect::<BTreeSet<&char>>().len() != 4 {
+            return Err(format!(
+                "Boarding pass letters must be distinct: {}",
+                letters.iter().collect::<String>()
+            ));
+        }
+
+        Ok(SeatPlane {
+            row_bits,
+            column_bits,
+            row_letters: (letters[0], letters[1]),
+            column_letters: (letters[2], letters[3]),
+        })
+    }
+
+    fn num_rows(&self) -> u32 {
+        1 << self.row_bits
+    }
+
+    fn num_columns(&self) -> u32 {
+        1 << self.column_bits
+    }
+
+    fn max_id(&self) -> u32 {
+        self.num_rows() * self.num_columns() - 1
+    }
+}
+
+impl Default for SeatPlane {
+    fn default() -> SeatPlane {
+        SeatPlane::new(7, 3, "FBLR").unwrap()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum VerticalDirection {
     Up,
     Down,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum HorizontalDirection {
     Left,
     Right,
 }
 
+/// A rule that keeps otherwise open seats from being allocated.
 #[derive(Debug, PartialEq)]
-struct Position {
-    row: u32,
-    column: u32,
+pub enum BlockingRule {
+    /// Leaves an empty seat between each group and the passengers seated next to it.
+    EmptyMiddle,
+    /// Leaves the given columns empty in every row.
+    Columns(BTreeSet<u32>),
+}
+
+impl FromStr for BlockingRule {
+    type Err = String;
+
+    /// Parses a rule given as either "empty-middle" or "columns=" followed by a comma separated
+    /// list of columns (ex. "columns=3,4").
+    fn from_str(rule_str: &str) -> Result<BlockingRule, String> {
+        if rule_str == "empty-middle" {
+            return Ok(BlockingRule::EmptyMiddle);
+        }
+
+        match rule_str.strip_prefix("columns=") {
+            Some(columns_str) => columns_str
+                .split(',')
+                .map(|c| {
+                    c.trim()
+                        .parse()
+                        .map_err(|_| format!("Invalid column in blocking rule: {}", c))
+                })
+                .collect::<Result<BTreeSet<u32>, String>>()
+                .map(BlockingRule::Columns),
+            None => Err(format!("Unknown blocking rule: {}", rule_str)),
+        }
+    }
+}
+
+/// Assigns seats to groups of passengers. Each group is seated in contiguous seats within a single
+/// row if possible, and otherwise in the seats that span the fewest rows.
+pub struct SeatAllocator {
+    plane: SeatPlane,
+    filled: BTreeSet<u32>,
+    rules: Vec<BlockingRule>,
+}
+
+impl SeatAllocator {
+    pub fn new(
+        seat_locations: &[SeatLocation],
+        plane: &SeatPlane,
+        rules: Vec<BlockingRule>,
+    ) -> SeatAllocator {
+        SeatAllocator {
+            plane: *plane,
+            filled: seat_locations
+                .iter()
+                .map(|l| l.to_position().to_id(plane))
+                .collect(),
+            rules,
+        }
+    }
+
+    /// Assigns seats to a group of the given size and returns their boarding passes, in seat ID
+    /// order. The seats are then treated as filled for any later groups.
+    pub fn allocate(&mut self, group_size: u32) -> Result<Vec<SeatLocation>, String> {
+        if group_size == 0 {
+            return Err("Group must have at least one passenger".to_string());
+        }
+
+        let rows: Vec<Vec<u32>> = (0..self.plane.num_rows())
+            .map(|row| self.available_in_row(row))
+            .collect();
+        let seats = find_contiguous_seats(&rows, group_size as usize)
+            .or_else(|| find_least_spread_seats(&rows, group_size as usize))
+            .ok_or_else(|| format!("Not enough open seats for a group of {}", group_size))?;
+
+        self.filled.extend(seats.iter());
+        Ok(seats
+            .iter()
+            .map(|id| SeatLocation::from_id(*id, &self.plane).unwrap())
+            .collect())
+    }
+
+    fn available_in_row(&self, row: u32) -> Vec<u32> {
+        (0..self.plane.num_columns())
+            .filter(|column| self.is_available(row, *column))
+            .map(|column| Position { row, column }.to_id(&self.plane))
+            .collect()
+    }
+
+    fn is_available(&self, row: u32, column: u32) -> bool {
+        let id = Position { row, column }.to_id(&self.plane);
+        if self.filled.contains(&id) {
+            return false;
+        }
+
+        self.rules.iter().all(|rule| match rule {
+            BlockingRule::EmptyMiddle => {
+                let left_filled = column > 0 && self.filled.contains(&(id - 1));
+                let right_filled =
+                    column + 1 < self.plane.num_columns() && self.filled.contains(&(id + 1));
+
+                !left_filled && !right_filled
+            }
+            BlockingRule::Columns(columns) => !columns.contains(&column),
+        })
+    }
+}
+
+/// Returns the first run of contiguous open seats within a single row.
+fn find_contiguous_seats(rows: &[Vec<u32>], group_size: usize) -> Option<Vec<u32>> {
+    rows.iter()
+        .flat_map(|row| row.windows(group_size))
+        .find(|seats| seats[group_size - 1] - seats[0] == group_size as u32 - 1)
+        .map(|seats| seats.to_vec())
+}
+
+/// Returns the first open seats that fit the group within the fewest consecutive rows.
+fn find_least_spread_seats(rows: &[Vec<u32>], group_size: usize) -> Option<Vec<u32>> {
+    let mut best: Option<(usize, usize)> = None;
+    for start in 0..rows.len() {
+        let mut num_seats = 0;
+        for (end, row) in rows.iter().enumerate().skip(start) {
+            num_seats += row.len();
+            if num_seats >= group_size {
+                let spread = end - start;
+                if !matches!(best, Some((best_spread, _)) if best_spread <= spread) {
+                    best = Some((spread, start));
+                }
+                break;
+            }
+        }
+    }
+
+    best.map(|(_, start)| {
+        rows[start..]
+            .iter()
+            .flatten()
+            .take(group_size)
+            .cloned()
+            .collect()
+    })
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Position {
+    pub row: u32,
+    pub column: u32,
 }
 
 impl Position {
-    fn to_id(&self) -> u32 {
-        self.row * 8 + self.column
+    pub fn from_id(id: u32, plane: &SeatPlane) -> Result<Position, String> {
+        if id > plane.max_id() {
+            return Err(format!(
+                "Seat ID {} is outside of the plane, which ends at {}",
+                id,
+                plane.max_id()
+            ));
+        }
+
+        Ok(Position {
+            row: id / plane.num_columns(),
+            column: id % plane.num_columns(),
+        })
+    }
+
+    pub fn to_id(&self, plane: &SeatPlane) -> u32 {
+        self.row * plane.num_columns() + self.column
     }
 }
 
-struct SeatLocation {
+#[derive(Debug, PartialEq)]
+pub struct SeatLocation {
+    plane: SeatPlane,
     vertical_directions: Vec<VerticalDirection>,
     horizontal_directions: Vec<HorizontalDirection>,
 }
 
 impl SeatLocation {
-    fn from_str(seat_location_str: &str) -> SeatLocation {
+    pub fn from_id(id: u32, plane: &SeatPlane) -> Result<SeatLocation, String> {
+        SeatLocation::from_position(&Position::from_id(id, plane)?, plane)
+    }
+
+    /// Creates the boarding pass for the given seat. Decoding the boarding pass gives back the
+    /// same position.
+    pub fn from_position(position: &Position, plane: &SeatPlane) -> Result<SeatLocation, String> {
+        if position.row >= plane.num_rows() || position.column >= plane.num_columns() {
+            return Err(format!(
+                "Seat at row {} column {} is outside of the plane, which has {} rows and {} columns",
+                position.row,
+                position.column,
+                plane.num_rows(),
+                plane.num_columns()
+            ));
+        }
+
+        let vertical_directions = (0..plane.row_bits)
+            .rev()
+            .map(|bit| {
+                if (position.row >> bit) & 1 == 1 {
+                    VerticalDirection::Down
+                } else {
+                    VerticalDirection::Up
+                }
+            })
+            .collect();
+        let horizontal_directions = (0..plane.column_bits)
+            .rev()
+            .map(|bit| {
+                if (position.column >> bit) & 1 == 1 {
+                    HorizontalDirection::Right
+                } else {
+                    HorizontalDirection::Left
+                }
+            })
+            .collect();
+
+        Ok(SeatLocation {
+            plane: *plane,
+            vertical_directions,
+            horizontal_directions,
+        })
+    }
+
+    pub fn from_str(seat_location_str: &str, plane: &SeatPlane) -> Result<SeatLocation, String> {
+        let expected_length = (plane.row_bits + plane.column_bits) as usize;
+        if seat_location_str.chars().count() != expected_length {
+            return Err(format!(
+                "Boarding pass {} has {} letters, expected {}",
+                seat_location_str,
+                seat_location_str.chars().count(),
+                expected_length
+            ));
+        }
+
         let mut vertical_directions: Vec<VerticalDirection> = vec![];
         let mut horizontal_directions: Vec<HorizontalDirection> = vec![];
 
-        for c in seat_location_str.chars() {
-            match c {
-                'F' => vertical_directions.push(VerticalDirection::Up),
-                'B' => vertical_directions.push(VerticalDirection::Down),
-                'L' => horizontal_directions.push(HorizontalDirection::Left),
-                'R' => horizontal_directions.push(HorizontalDirection::Right),
-                _ => panic!(),
+        for (i, c) in seat_location_str.chars().enumerate() {
+            let is_row = i < plane.row_bits as usize;
+            let (lower_letter, upper_letter) = if is_row {
+                plane.row_letters
+            } else {
+                plane.column_letters
+            };
+
+            let is_upper = if c == lower_letter {
+                false
+            } else if c == upper_letter {
+                true
+            } else {
+                return Err(format!(
+                    "Invalid {} letter {} at position {} of boarding pass {}",
+                    if is_row { "row" } else { "column" },
+                    c,
+                    i + 1,
+                    seat_location_str
+                ));
+            };
+
+            match (is_row, is_upper) {
+                (true, false) => vertical_directions.push(VerticalDirection::Up),
+                (true, true) => vertical_directions.push(VerticalDirection::Down),
+                (false, false) => horizontal_directions.push(HorizontalDirection::Left),
+                (false, true) => horizontal_directions.push(HorizontalDirection::Right),
             }
         }
 
-        SeatLocation {
+        Ok(SeatLocation {
+            plane: *plane,
             vertical_directions,
             horizontal_directions,
-        }
+        })
     }
 
-    fn to_position(&self) -> Position {
+    pub fn to_position(&self) -> Position {
         let mut row = 0;
         for direction in self.vertical_directions.iter() {
             match direction {
@@ -84,39 +476,197 @@ impl SeatLocation {
     }
 }
 
-fn read_input<R>(reader: R) -> Vec<SeatLocation>
+impl fmt::Display for SeatLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (front, back) = self.plane.row_letters;
+        let (left, right) = self.plane.column_letters;
+
+        for direction in self.vertical_directions.iter() {
+            match direction {
+                VerticalDirection::Up => write!(f, "{}", front)?,
+                VerticalDirection::Down => write!(f, "{}", back)?,
+            }
+        }
+        for direction in self.horizontal_directions.iter() {
+            match direction {
+                HorizontalDirection::Left => write!(f, "{}", left)?,
+                HorizontalDirection::Right => write!(f, "{}", right)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn read_input<R>(reader: R, plane: &SeatPlane) -> Result<Vec<SeatLocation>, String>
 where
     R: BufRead,
 {
     reader
         .lines()
-        .map(|l| SeatLocation::from_str(&l.unwrap()))
+        .enumerate()
+        .map(|(i, l)| {
+            SeatLocation::from_str(&l.unwrap(), plane)
+                .map_err(|msg| format!("Line {}: {}", i + 1, msg))
+        })
         .collect()
 }
 
-fn get_highest_seat_id(seat_locations: &[SeatLocation]) -> u32 {
+/// An inclusive range of seat IDs.
+type SeatRun = (u32, u32);
+
+/// The number of boarding passes for each seat in a plane.
+struct Occupancy {
+    plane: SeatPlane,
+    passes: BTreeMap<u32, usize>,
+}
+
+impl Occupancy {
+    fn new(seat_locations: &[SeatLocation], plane: &SeatPlane) -> Occupancy {
+        let mut passes = BTreeMap::new();
+        for location in seat_locations.iter() {
+            *passes
+                .entry(location.to_position().to_id(plane))
+                .or_insert(0) += 1;
+        }
+
+        Occupancy {
+            plane: *plane,
+            passes,
+        }
+    }
+
+    fn is_filled(&self, id: u32) -> bool {
+        self.passes.contains_key(&id)
+    }
+
+    /// Returns the seats that are missing at the front and at the back of the plane, before the
+    /// first and after the last filled seat. If no seats are filled, the whole plane is the front.
+    fn missing_runs(&self) -> (Option<SeatRun>, Option<SeatRun>) {
+        let first = match self.passes.keys().next() {
+            Some(first) => *first,
+            None => return (Some((0, self.plane.max_id())), None),
+        };
+        let last = *self.passes.keys().next_back().unwrap();
+
+        let front = if first > 0 {
+            Some((0, first - 1))
+        } else {
+            None
+        };
+        let back = if last < self.plane.max_id() {
+            Some((last + 1, self.plane.max_id()))
+        } else {
+            None
+        };
+
+        (front, back)
+    }
+
+    /// Returns the empty seats that are not part of the missing runs at the front or back.
+    fn empty_seats(&self) -> Vec<u32> {
+        match (self.passes.keys().next(), self.passes.keys().next_back()) {
+            (Some(first), Some(last)) => {
+                (*first..*last).filter(|id| !self.is_filled(*id)).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    fn duplicates(&self) -> Vec<(u32, usize)> {
+        self.passes
+            .iter()
+            .filter(|(_, count)| **count > 1)
+            .map(|(id, count)| (*id, *count))
+            .collect()
+    }
+
+    /// Returns the empty seats that have filled seats on both sides, any of which could be ours.
+    fn candidates(&self) -> Vec<u32> {
+        self.empty_seats()
+            .into_iter()
+            .filter(|id| *id > 0 && self.is_filled(id - 1) && self.is_filled(id + 1))
+            .collect()
+    }
+
+    /// Renders one line per row, using "#" for filled seats, "." for empty seats, "D" for seats with
+    /// duplicate boarding passes, and "?" for candidates for our seat.
+    fn render(&self) -> String {
+        let candidates: BTreeSet<u32> = self.candidates().into_iter().collect();
+        let row_width = format!("{}", self.plane.num_rows() - 1).len();
+
+        let mut rendered = String::new();
+        for row in 0..self.plane.num_rows() {
+            rendered.push_str(&format!("{:>width$} ", row, width = row_width));
+            for column in 0..self.plane.num_columns() {
+                let id = Position { row, column }.to_id(&self.plane);
+                rendered.push(match self.passes.get(&id) {
+                    Some(1) => '#',
+                    Some(_) => 'D',
+                    None if candidates.contains(&id) => '?',
+                    None => '.',
+                });
+            }
+            rendered.push('\n');
+        }
+
+        rendered
+    }
+
+    fn report(&self) -> String {
+        let mut report = String::new();
+
+        let (front, back) = self.missing_runs();
+        if let Some((start, end)) = front {
+            report.push_str(&format!("Missing at front: {}-{}\n", start, end));
+        }
+        if let Some((start, end)) = back {
+            report.push_str(&format!("Missing at back: {}-{}\n", start, end));
+        }
+
+        for id in self.empty_seats() {
+            report.push_str(&format!("Empty: {}\n", self.describe(id)));
+        }
+        for (id, count) in self.duplicates() {
+            report.push_str(&format!(
+                "Duplicate: {} ({} boarding passes)\n",
+                self.describe(id),
+                count
+            ));
+        }
+        for id in self.candidates() {
+            report.push_str(&format!("Candidate: {}\n", self.describe(id)));
+        }
+
+        report
+    }
+
+    fn describe(&self, id: u32) -> String {
+        let position = Position::from_id(id, &self.plane).unwrap();
+        let location = SeatLocation::from_position(&position, &self.plane).unwrap();
+
+        format!(
+            "{} (row {} column {}, {})",
+            id, position.row, position.column, location
+        )
+    }
+}
+
+fn get_highest_seat_id(seat_locations: &[SeatLocation], plane: &SeatPlane) -> Option<u32> {
     seat_locations
         .iter()
-        .map(|l| l.to_position().to_id())
+        .map(|l| l.to_position().to_id(plane))
         .max()
-        .unwrap()
 }
 
-fn get_open_seat_id(seat_locations: &[SeatLocation]) -> u32 {
+fn get_open_seat_id(seat_locations: &[SeatLocation], plane: &SeatPlane) -> Option<u32> {
     let filled_seats: BTreeSet<u32> = seat_locations
         .iter()
-        .map(|l| l.to_position().to_id())
+        .map(|l| l.to_position().to_id(plane))
         .collect();
 
-    let second_seat = Position { row: 0, column: 1 };
-    let penultimate_seat = Position {
-        row: 127,
-        column: 62,
-    };
-
-    for id in second_seat.to_id()..penultimate_seat.to_id() {
-        assert!(id != 0);
-
+    // The first and last seats cannot be ours, since they are missing a neighbor
+    for id in 1..plane.max_id() {
         let prev = id - 1;
         let next = id + 1;
 
@@ -124,10 +674,10 @@ fn get_open_seat_id(seat_locations: &[SeatLocation]) -> u32 {
             && filled_seats.contains(&prev)
             && filled_seats.contains(&next)
         {
-            return id;
+            return Some(id);
         }
     }
-    panic!()
+    None
 }
 
 #[cfg(test)]
@@ -136,7 +686,8 @@ mod tests {
 
     #[test]
     fn seat_location_to_id() {
-        let location = SeatLocation::from_str("BFFFBBFRRR");
+        let plane = SeatPlane::default();
+        let location = SeatLocation::from_str("BFFFBBFRRR", &plane).unwrap();
 
         let expected_position = Position { row: 70, column: 7 };
         let position = location.to_position();
@@ -144,8 +695,234 @@ mod tests {
         assert_eq!(expected_position, position);
 
         let expected_id = 567;
-        let id = position.to_id();
+        let id = position.to_id(&plane);
 
         assert_eq!(expected_id, id);
     }
+
+    #[test]
+    fn seat_location_custom_plane() {
+        let plane = SeatPlane::new(2, 4, "ABCD").unwrap();
+        let location = SeatLocation::from_str("BADCDC", &plane).unwrap();
+
+        let expected_position = Position { row: 2, column: 10 };
+        let position = location.to_position();
+
+        assert_eq!(expected_position, position);
+        assert_eq!(42, position.to_id(&plane));
+        assert_eq!(63, plane.max_id());
+    }
+
+    #[test]
+    fn seat_location_wrong_length() {
+        let plane = SeatPlane::default();
+
+        assert!(SeatLocation::from_str("BFFFBBFRR", &plane).is_err());
+        assert!(SeatLocation::from_str("BFFFBBFRRRR", &plane).is_err());
+    }
+
+    #[test]
+    fn seat_location_wrong_letters() {
+        let plane = SeatPlane::default();
+
+        assert!(SeatLocation::from_str("BFFFBBRRRR", &plane).is_err());
+        assert!(SeatLocation::from_str("BFFFBBFRRB", &plane).is_err());
+        assert!(SeatLocation::from_str("BFFFBBFRRX", &plane).is_err());
+    }
+
+    #[test]
+    fn seat_plane_invalid() {
+        assert!(SeatPlane::new(7, 3, "FBL").is_err());
+        assert!(SeatPlane::new(7, 3, "FBLF").is_err());
+        assert!(SeatPlane::new(20, 12, "FBLR").is_err());
+        assert!(SeatPlane::new(u32::MAX, 1, "FBLR").is_err());
+    }
+
+    #[test]
+    fn seat_location_from_position() {
+        let plane = SeatPlane::default();
+        let location =
+            SeatLocation::from_position(&Position { row: 70, column: 7 }, &plane).unwrap();
+
+        assert_eq!("BFFFBBFRRR", location.to_string());
+        assert_eq!(
+            "FBFBBFFRLR",
+            SeatLocation::from_id(357, &plane).unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn seat_location_outside_plane() {
+        let plane = SeatPlane::default();
+
+        assert!(SeatLocation::from_id(1024, &plane).is_err());
+        assert!(SeatLocation::from_position(
+            &Position {
+                row: 128,
+                column: 0
+            },
+            &plane
+        )
+        .is_err());
+        assert!(SeatLocation::from_position(&Position { row: 0, column: 8 }, &plane).is_err());
+    }
+
+    #[test]
+    fn seat_location_round_trip_every_seat() {
+        for plane in [SeatPlane::default(), SeatPlane::new(3, 5, "UDab").unwrap()].iter() {
+            for id in 0..=plane.max_id() {
+                let pass = SeatLocation::from_id(id, plane).unwrap().to_string();
+                let decoded = SeatLocation::from_str(&pass, plane).unwrap();
+
+                assert_eq!(id, decoded.to_position().to_id(plane));
+                assert_eq!(pass, decoded.to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn parse_seat_formats() {
+        assert_eq!(Some((357, None)), parse_seat("357"));
+        assert_eq!(Some((44, Some(5))), parse_seat("44, 5"));
+        assert_eq!(None, parse_seat("44,5,1"));
+        assert_eq!(None, parse_seat("seat"));
+    }
+
+    #[test]
+    fn get_highest_seat_id_none() {
+        assert_eq!(None, get_highest_seat_id(&[], &SeatPlane::default()));
+    }
+
+    #[test]
+    fn get_open_seat_id_none() {
+        let plane = SeatPlane::new(1, 2, "FBLR").unwrap();
+        let seat_locations = seats(&[0, 1, 2], &plane);
+
+        assert_eq!(None, get_open_seat_id(&seat_locations, &plane));
+    }
+
+    #[test]
+    fn occupancy_report() {
+        let plane = SeatPlane::new(2, 2, "FBLR").unwrap();
+        let seat_locations = seats(&[2, 3, 3, 5, 7, 8, 10], &plane);
+        let occupancy = Occupancy::new(&seat_locations, &plane);
+
+        assert_eq!((Some((0, 1)), Some((11, 15))), occupancy.missing_runs());
+        assert_eq!(vec![4, 6, 9], occupancy.empty_seats());
+        assert_eq!(vec![(3, 2)], occupancy.duplicates());
+        assert_eq!(vec![4, 6, 9], occupancy.candidates());
+        assert_eq!(Some(4), get_open_seat_id(&seat_locations, &plane));
+
+        assert_eq!("0 ..#D\n1 ?#?#\n2 #?#.\n3 ....\n", occupancy.render());
+
+        let report = occupancy.report();
+        assert!(report.starts_with("Missing at front: 0-1\nMissing at back: 11-15\n"));
+        assert!(report.contains("Empty: 4 (row 1 column 0, FBLL)\n"));
+        assert!(report.contains("Duplicate: 3 (row 0 column 3, FFRR) (2 boarding passes)\n"));
+        assert!(report.contains("Candidate: 9 (row 2 column 1, BFLR)\n"));
+    }
+
+    #[test]
+    fn occupancy_empty_plane() {
+        let plane = SeatPlane::new(1, 1, "FBLR").unwrap();
+        let occupancy = Occupancy::new(&[], &plane);
+
+        assert_eq!((Some((0, 3)), None), occupancy.missing_runs());
+        assert!(occupancy.empty_seats().is_empty());
+        assert!(occupancy.candidates().is_empty());
+    }
+
+    fn seats(ids: &[u32], plane: &SeatPlane) -> Vec<SeatLocation> {
+        ids.iter()
+            .map(|id| SeatLocation::from_id(*id, plane).unwrap())
+            .collect()
+    }
+
+    fn seat_ids(seats: &[SeatLocation], plane: &SeatPlane) -> Vec<u32> {
+        seats.iter().map(|s| s.to_position().to_id(plane)).collect()
+    }
+
+    #[test]
+    fn blocking_rule_from_str() {
+        assert_eq!(Ok(BlockingRule::EmptyMiddle), "empty-middle".parse());
+        assert_eq!(
+            Ok(BlockingRule::Columns([3, 4].iter().cloned().collect())),
+            "columns=3,4".parse()
+        );
+        assert!("columns=a".parse::<BlockingRule>().is_err());
+        assert!("aisles".parse::<BlockingRule>().is_err());
+    }
+
+    #[test]
+    fn allocate_contiguous_in_row() {
+        let plane = SeatPlane::new(2, 2, "FBLR").unwrap();
+        let filled = seats(&[1, 6], &plane);
+        let mut allocator = SeatAllocator::new(&filled, &plane, vec![]);
+
+        let seats = allocator.allocate(2).unwrap();
+        assert_eq!(vec![2, 3], seat_ids(&seats, &plane));
+        assert_eq!("FFRL", seats[0].to_string());
+
+        assert_eq!(
+            vec![8, 9, 10],
+            seat_ids(&allocator.allocate(3).unwrap(), &plane)
+        );
+        assert_eq!(
+            vec![4, 5],
+            seat_ids(&allocator.allocate(2).unwrap(), &plane)
+        );
+    }
+
+    #[test]
+    fn allocate_least_spread() {
+        let plane = SeatPlane::new(2, 2, "FBLR").unwrap();
+        let filled = seats(&[1, 2, 5, 6, 9, 10, 13], &plane);
+        let mut allocator = SeatAllocator::new(&filled, &plane, vec![]);
+
+        assert_eq!(
+            vec![14, 15],
+            seat_ids(&allocator.allocate(2).unwrap(), &plane)
+        );
+        assert_eq!(
+            vec![0, 3, 4],
+            seat_ids(&allocator.allocate(3).unwrap(), &plane)
+        );
+        assert_eq!(
+            vec![7, 8, 11, 12],
+            seat_ids(&allocator.allocate(4).unwrap(), &plane)
+        );
+        assert!(allocator.allocate(1).is_err());
+    }
+
+    #[test]
+    fn allocate_with_blocking_rules() {
+        let plane = SeatPlane::new(1, 3, "FBLR").unwrap();
+        let filled = seats(&[2], &plane);
+        let mut allocator = SeatAllocator::new(&filled, &plane, vec![BlockingRule::EmptyMiddle]);
+
+        assert_eq!(
+            vec![4, 5, 6, 7],
+            seat_ids(&allocator.allocate(4).unwrap(), &plane)
+        );
+        assert_eq!(
+            vec![8, 9],
+            seat_ids(&allocator.allocate(2).unwrap(), &plane)
+        );
+        assert_eq!(
+            vec![11, 12],
+            seat_ids(&allocator.allocate(2).unwrap(), &plane)
+        );
+        assert!(allocator.allocate(4).is_err());
+        assert_eq!(
+            vec![0, 14, 15],
+            seat_ids(&allocator.allocate(3).unwrap(), &plane)
+        );
+
+        let columns = BlockingRule::Columns([1, 2].iter().cloned().collect());
+        let mut allocator = SeatAllocator::new(&[], &plane, vec![columns]);
+        assert_eq!(
+            vec![3, 4, 5, 6, 7],
+            seat_ids(&allocator.allocate(5).unwrap(), &plane)
+        );
+    }
 }
@@ -1,222 +1,1401 @@
 extern crate regex;
 
-use std::io::{self, BufRead};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::str::FromStr;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use self::regex::Regex;
+
+/// Schema for the passport fields described in the puzzle.
+const DEFAULT_SCHEMA: &str = "byr required int 1920-2002 digits=4
+iyr required int 2010-2020 digits=4
+eyr required int 2020-2030 digits=4
+hgt required unit 150-193cm 59-76in
+hcl required regex ^#[0-9a-f]{6}$
+ecl required enum amb blu brn gry grn hzl oth
+pid required regex ^[0-9]{9}$
+cid optional any";
+
+/// Number of passports to send to a validation thread at a time.
+const BATCH_SIZE: usize = 1024;
 
 pub fn part_one() {
-    let passports = read_input(io::stdin().lock());
-    let answer = count_passports_with_required_fields(&passports);
-
-    println!("{}", answer)
+    part_one_with_options(&InputOptions::default())
 }
 
 pub fn part_two() {
-    let passports = read_input(io::stdin().lock());
-    let answer = count_valid_passports(&passports);
+    part_two_with_options(&InputOptions::default())
+}
 
-    println!("{}", answer)
+pub fn part_one_with_options(options: &InputOptions) {
+    match validate_input(options) {
+        Ok(counts) => println!("{}", counts.with_required_fields),
+        Err(msg) => println!("{}", msg),
+    }
 }
 
-struct Passport {
-    birth_year: Option<String>,
-    issue_year: Option<String>,
-    expiration_year: Option<String>,
-    height: Option<String>,
-    hair_color: Option<String>,
-    eye_color: Option<String>,
-    passport_id: Option<String>,
-    _country_id: Option<String>,
+pub fn part_two_with_options(options: &InputOptions) {
+    match validate_input(options) {
+        Ok(counts) => println!("{}", counts.valid),
+        Err(msg) => println!("{}", msg),
+    }
 }
 
-impl Passport {
-    pub fn from_lines(lines: &[String]) -> Passport {
-        let mut birth_year: Option<String> = None;
-        let mut issue_year: Option<String> = None;
-        let mut expiration_year: Option<String> = None;
-        let mut height: Option<String> = None;
-        let mut hair_color: Option<String> = None;
-        let mut eye_color: Option<String> = None;
-        let mut passport_id: Option<String> = None;
-        let mut country_id: Option<String> = None;
-
-        let parts: Vec<String> = lines
-            .iter()
-            .flat_map(|l| l.split(' '))
-            .map(|s| s.to_string())
-            .collect();
+/// Prints each passport with the status of each of its fields, followed by the number of passports
+/// rejected by each rule.
+pub fn report(options: &InputOptions) {
+    let (schema, passports) = match load(options) {
+        Ok(loaded) => loaded,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+
+    print!("{}", validation_report(&passports, &schema));
+}
+
+/// Prints each passport as a normalized record in the given format.
+pub fn export(options: &InputOptions, format: ExportFormat) {
+    let (schema, passports) = match load(options) {
+        Ok(loaded) => loaded,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+
+    let records: Vec<NormalizedRecord> = passports
+        .iter()
+        .enumerate()
+        .map(|(i, p)| NormalizedRecord::from_passport(i + 1, p, &schema))
+        .collect();
+
+    match format {
+        ExportFormat::JsonLines => {
+            for record in records.iter() {
+                println!("{}", record.to_json());
+            }
+        }
+        ExportFormat::Csv => {
+            println!("{}", csv_header(&schema));
+            for record in records.iter() {
+                println!("{}", record.to_csv_row());
+            }
+        }
+    }
+}
+
+/// Prints the passports across the given batch files that share a passport ID along with how their
+/// fields differ, followed by the passports that match on every field except one. Fields that are
+/// not in the schema are compared as well, when parsing leniently.
+pub fn duplicates(options: &InputOptions, batch_paths: &[&str]) {
+    let passports = match load_batches(options, batch_paths) {
+        Ok(passports) => passports,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+
+    println!("shared passport ids:");
+    for (passport_id, group) in group_by_passport_id(&passports) {
+        println!("  pid {}:", passport_id);
+        for i in group.iter() {
+            println!("    {}", passports[*i]);
+        }
+
+        let group_passports: Vec<&Passport> =
+            group.iter().map(|i| &passports[*i].passport).collect();
+        for (key, values) in field_differences(&group_passports) {
+            let values: Vec<&str> = values.iter().map(|v| v.unwrap_or("(missing)")).collect();
+            println!("    {} differs: {}", key, values.join(" / "));
+        }
+    }
+
+    println!("near-duplicates:");
+    for (a, b, key) in find_near_duplicates(&passports) {
+        let a_passport = &passports[a];
+        let b_passport = &passports[b];
+        let value = |p: &BatchPassport| {
+            p.passport
+                .any_field(&key)
+                .map_or("(missing)", |v| v.as_str())
+                .to_string()
+        };
+
+        println!(
+            "  {} and {} differ only on {}: {} / {}",
+            a_passport,
+            b_passport,
+            key,
+            value(a_passport),
+            value(b_passport)
+        );
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    JsonLines,
+    Csv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(format_str: &str) -> Result<ExportFormat, String> {
+        match format_str {
+            "jsonl" => Ok(ExportFormat::JsonLines),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format!("Unknown export format: {}", format_str)),
+        }
+    }
+}
+
+pub struct InputOptions<'a> {
+    /// File to read the schema from, instead of using the puzzle's schema.
+    pub schema_path: Option<&'a str>,
+    pub parse_mode: ParseMode,
+    /// Number of threads to validate passports on.
+    pub num_threads: usize,
+}
+
+impl<'a> Default for InputOptions<'a> {
+    fn default() -> InputOptions<'a> {
+        InputOptions {
+            schema_path: None,
+            parse_mode: ParseMode::Strict,
+            num_threads: thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+        }
+    }
+}
+
+/// How to handle passports that contain unknown, duplicate, or malformed fields.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseMode {
+    /// Fail on the first problem found.
+    Strict,
+    /// Keep unknown fields as extras, keep the last value of duplicate fields, and skip malformed
+    /// fields, recording a warning for each.
+    Lenient,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct ValidationCounts {
+    total: usize,
+    with_required_fields: usize,
+    valid: usize,
+}
+
+impl ValidationCounts {
+    fn add(&mut self, other: &ValidationCounts) {
+        self.total += other.total;
+        self.with_required_fields += other.with_required_fields;
+        self.valid += other.valid;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct ParseError {
+    /// Index of the passport in the input, starting from 1.
+    record: usize,
+    line: usize,
+    token: String,
+    reason: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Passport {} (line {}): {}: {}",
+            self.record, self.line, self.reason, self.token
+        )
+    }
+}
+
+enum Validator {
+    /// Accepts any value.
+    Any,
+    /// An integer within an inclusive range, optionally with an exact number of digits.
+    Integer {
+        min: i64,
+        max: i64,
+        digits: Option<usize>,
+    },
+    /// A value that matches a regular expression.
+    Pattern(Regex),
+    /// One of a fixed set of values.
+    OneOf(Vec<String>),
+    /// An integer followed by a unit, where each unit has its own inclusive range.
+    UnitRange(Vec<(String, i64, i64)>),
+}
+
+impl Validator {
+    fn from_str(kind: &str, args: &str) -> Result<Validator, String> {
+        let range_regex = Regex::new(r"^(-?\d+)-(-?\d+)([^\d\s]*)$").unwrap();
+        let parse_range = |range_str: &str| match range_regex.captures(range_str) {
+            None => Err(format!("Invalid range: {}", range_str)),
+            Some(capture) => Ok((
+                capture.get(1).unwrap().as_str().parse::<i64>().unwrap(),
+                capture.get(2).unwrap().as_str().parse::<i64>().unwrap(),
+                capture.get(3).unwrap().as_str().to_string(),
+            )),
+        };
+
+        let args_list: Vec<&str> = args.split_whitespace().collect();
+        match kind {
+            "any" => Ok(Validator::Any),
+            "int" => {
+                if args_list.is_empty() || args_list.len() > 2 {
+                    return Err(format!("Invalid int validator arguments: {}", args));
+                }
+
+                let (min, max, unit) = parse_range(args_list[0])?;
+                if !unit.is_empty() {
+                    return Err(format!("Unexpected unit in int range: {}", args_list[0]));
+                }
+
+                let digits = match args_list.get(1) {
+                    None => None,
+                    Some(digits_str) => match digits_str.strip_prefix("digits=") {
+                        Some(d) => match d.parse::<usize>() {
+                            Ok(d) => Some(d),
+                            Err(_) => return Err(format!("Invalid digit count: {}", digits_str)),
+                        },
+                        None => return Err(format!("Unknown int argument: {}", digits_str)),
+                    },
+                };
+
+                Ok(Validator::Integer { min, max, digits })
+            }
+            "regex" => match Regex::new(args.trim()) {
+                Ok(regex) => Ok(Validator::Pattern(regex)),
+                Err(err) => Err(format!("Invalid regex {}: {}", args.trim(), err)),
+            },
+            "enum" => {
+                if args_list.is_empty() {
+                    return Err("Enum validator requires at least one value".to_string());
+                }
+
+                Ok(Validator::OneOf(
+                    args_list.iter().map(|a| a.to_string()).collect(),
+                ))
+            }
+            "unit" => {
+                if args_list.is_empty() {
+                    return Err("Unit validator requires at least one range".to_string());
+                }
+
+                let mut ranges: Vec<(String, i64, i64)> = vec![];
+                for range_str in args_list.iter() {
+                    let (min, max, unit) = parse_range(range_str)?;
+                    if unit.is_empty() {
+                        return Err(format!("Missing unit in range: {}", range_str));
+                    }
+
+                    ranges.push((unit, min, max));
+                }
+
+                Ok(Validator::UnitRange(ranges))
+            }
+            _ => Err(format!("Unknown validator: {}", kind)),
+        }
+    }
+
+    /// Checks the given value of the field with the given key, returning the reason the value is
+    /// invalid if it is not valid.
+    fn validate(&self, key: &str, value: &str) -> Result<(), String> {
+        match self {
+            Validator::Any => Ok(()),
+            Validator::Integer { min, max, digits } => {
+                if let Some(digits) = digits {
+                    if value.len() != *digits {
+                        return Err(format!(
+                            "{} {} has {} digits, expected {}",
+                            key,
+                            value,
+                            value.len(),
+                            digits
+                        ));
+                    }
+                }
+
+                match value.parse::<i64>() {
+                    Ok(num) => check_range(key, value, num, *min, *max, ""),
+                    Err(_) => Err(format!("{} {} is not an integer", key, value)),
+                }
+            }
+            Validator::Pattern(regex) => {
+                if regex.is_match(value) {
+                    Ok(())
+                } else {
+                    Err(format!("{} {} does not match {}", key, value, regex))
+                }
+            }
+            Validator::OneOf(options) => {
+                if options.iter().any(|o| o == value) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "{} {} is not one of {}",
+                        key,
+                        value,
+                        options.join(", ")
+                    ))
+                }
+            }
+            Validator::UnitRange(ranges) => {
+                // Units can be suffixes of each other (ex. "m" and "cm"), so only accept a unit
+                // if the rest of the value is a number
+                let mut has_unit_suffix = false;
+                for (unit, min, max) in ranges.iter() {
+                    if let Some(num_str) = value.strip_suffix(unit.as_str()) {
+                        has_unit_suffix = true;
+
+                        if let Ok(num) = num_str.parse::<i64>() {
+                            return check_range(key, value, num, *min, *max, unit);
+                        }
+                    }
+                }
+
+                if has_unit_suffix {
+                    return Err(format!("{} {} is not an integer", key, value));
+                }
+
+                let units: Vec<&str> = ranges.iter().map(|(u, _, _)| u.as_str()).collect();
+                Err(format!(
+                    "{} {} does not have a unit of {}",
+                    key,
+                    value,
+                    units.join(" or ")
+                ))
+            }
+        }
+    }
+}
+
+fn check_range(
+    key: &str,
+    value: &str,
+    num: i64,
+    min: i64,
+    max: i64,
+    unit: &str,
+) -> Result<(), String> {
+    if num < min {
+        Err(format!(
+            "{} {} is below {}-{}{}",
+            key, value, min, max, unit
+        ))
+    } else if num > max {
+        Err(format!("{} {} exceeds {}-{}{}", key, value, min, max, unit))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum FieldStatus {
+    Missing,
+    Invalid(String),
+    Valid,
+}
+
+impl FieldStatus {
+    /// Returns whether a field with this status is acceptable under the given rule.
+    fn passes(&self, rule: &FieldRule) -> bool {
+        match self {
+            FieldStatus::Valid => true,
+            FieldStatus::Missing => !rule.required,
+            FieldStatus::Invalid(_) => false,
+        }
+    }
+}
+
+/// Keys of the columns that are written for every exported record, which schema fields cannot use.
+const RECORD_COLUMNS: [&str; 4] = ["record", "line", "valid", "errors"];
+
+/// Returns the length of the given unit in hundredths of a centimetre, if it is a known unit of
+/// length.
+fn hundredths_of_centimetre_per_unit(unit: &str) -> Option<i64> {
+    match unit {
+        "mm" => Some(10),
+        "cm" => Some(100),
+        "m" => Some(10000),
+        "in" => Some(254),
+        _ => None,
+    }
+}
+
+/// Formats the given length in hundredths of a centimetre as centimetres with two decimal places.
+fn format_centimetres(hundredths: i64) -> String {
+    let sign = if hundredths < 0 { "-" } else { "" };
+    let hundredths = hundredths.unsigned_abs();
+
+    format!("{}{}.{:02}", sign, hundredths / 100, hundredths % 100)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum NormalizedValue {
+    Integer(i64),
+    Length {
+        /// The length in hundredths of a centimetre, if the unit is a known unit of length.
+        hundredths_of_centimetre: Option<i64>,
+        value: i64,
+        unit: String,
+    },
+    Text(String),
+}
+
+impl NormalizedValue {
+    /// Converts the given field value into a more specific type based on how the field is
+    /// validated, falling back to text when the value cannot be converted.
+    fn from_value(validator: &Validator, value: &str) -> NormalizedValue {
+        match validator {
+            Validator::Integer { .. } => match value.parse::<i64>() {
+                Ok(num) => NormalizedValue::Integer(num),
+                Err(_) => NormalizedValue::Text(value.to_string()),
+            },
+            Validator::UnitRange(ranges) => {
+                for (unit, _, _) in ranges.iter() {
+                    if let Some(Ok(num)) =
+                        value.strip_suffix(unit.as_str()).map(|n| n.parse::<i64>())
+                    {
+                        return NormalizedValue::Length {
+                            hundredths_of_centimetre: hundredths_of_centimetre_per_unit(unit)
+                                .and_then(|per_unit| num.checked_mul(per_unit)),
+                            value: num,
+                            unit: unit.to_string(),
+                        };
+                    }
+                }
 
-        for part in parts.iter() {
-            let key_value: Vec<&str> = part.split(':').collect();
-            assert!(key_value.len() == 2);
-
-            let key = key_value[0];
-            let value = key_value[1];
-
-            match key {
-                "byr" => birth_year = Some(value.to_string()),
-                "iyr" => issue_year = Some(value.to_string()),
-                "eyr" => expiration_year = Some(value.to_string()),
-                "hgt" => height = Some(value.to_string()),
-                "hcl" => hair_color = Some(value.to_string()),
-                "ecl" => eye_color = Some(value.to_string()),
-                "pid" => passport_id = Some(value.to_string()),
-                "cid" => country_id = Some(value.to_string()),
-                _ => panic!(),
+                NormalizedValue::Text(value.to_string())
+            }
+            _ => {
+                let is_hex_color = value.len() > 1
+                    && value.starts_with('#')
+                    && value[1..].chars().all(|c| c.is_ascii_hexdigit());
+
+                if is_hex_color {
+                    NormalizedValue::Text(value.to_lowercase())
+                } else {
+                    NormalizedValue::Text(value.to_string())
+                }
             }
         }
+    }
 
-        Passport {
-            birth_year,
-            issue_year,
-            expiration_year,
-            height,
-            hair_color,
-            eye_color,
-            passport_id,
-            _country_id: country_id,
+    fn to_json(&self) -> String {
+        match self {
+            NormalizedValue::Integer(num) => num.to_string(),
+            NormalizedValue::Length {
+                hundredths_of_centimetre,
+                value,
+                unit,
+            } => format!(
+                "{{\"cm\":{},\"value\":{},\"unit\":{}}}",
+                hundredths_of_centimetre
+                    .map(format_centimetres)
+                    .unwrap_or_else(|| "null".to_string()),
+                value,
+                json_string(unit)
+            ),
+            NormalizedValue::Text(text) => json_string(text),
         }
     }
+}
+
+/// A passport converted into typed values, in the order of the fields in the schema.
+struct NormalizedRecord {
+    record: usize,
+    line: usize,
+    /// Reasons that the passport is not valid, empty if it is valid.
+    errors: Vec<String>,
+    fields: Vec<(String, bool, Option<NormalizedValue>)>,
+}
+
+impl NormalizedRecord {
+    fn from_passport(record: usize, passport: &Passport, schema: &Schema) -> NormalizedRecord {
+        let errors = passport
+            .check_fields(schema)
+            .iter()
+            .filter(|(rule, status)| !status.passes(rule))
+            .map(|(rule, status)| match status {
+                FieldStatus::Invalid(reason) => reason.clone(),
+                _ => format!("{} missing", rule.key),
+            })
+            .collect();
+
+        let fields = schema
+            .fields
+            .iter()
+            .map(|rule| {
+                let is_unit = matches!(rule.validator, Validator::UnitRange(_));
+                let value = passport
+                    .fields
+                    .get(&rule.key)
+                    .map(|v| NormalizedValue::from_value(&rule.validator, v));
+
+                (rule.key.clone(), is_unit, value)
+            })
+            .collect();
 
-    pub fn has_required_fields(&self) -> bool {
-        self.birth_year.is_some()
-            && self.issue_year.is_some()
-            && self.expiration_year.is_some()
-            && self.height.is_some()
-            && self.hair_color.is_some()
-            && self.eye_color.is_some()
-            && self.passport_id.is_some()
+        NormalizedRecord {
+            record,
+            line: passport.start_line,
+            errors,
+            fields,
+        }
     }
 
-    pub fn is_valid(&self) -> bool {
-        if !self.has_required_fields() {
-            return false;
+    fn to_json(&self) -> String {
+        let mut parts = vec![
+            format!("\"record\":{}", self.record),
+            format!("\"line\":{}", self.line),
+            format!("\"valid\":{}", self.errors.is_empty()),
+            format!(
+                "\"errors\":[{}]",
+                self.errors
+                    .iter()
+                    .map(|e| json_string(e))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ),
+        ];
+
+        for (key, _, value) in self.fields.iter() {
+            let value_json = match value {
+                None => "null".to_string(),
+                Some(v) => v.to_json(),
+            };
+
+            parts.push(format!("{}:{}", json_string(key), value_json));
         }
 
-        self.birth_year_is_valid()
-            && self.issue_year_is_valid()
-            && self.expiration_year_is_valid()
-            && self.height_is_valid()
-            && self.hair_color_is_valid()
-            && self.eye_color_is_valid()
-            && self.passport_id_is_valid()
+        format!("{{{}}}", parts.join(","))
     }
 
-    pub fn birth_year_is_valid(&self) -> bool {
-        if self.birth_year.as_ref().unwrap().len() != 4 {
-            return false;
+    fn to_csv_row(&self) -> String {
+        let mut columns = vec![
+            self.record.to_string(),
+            self.line.to_string(),
+            self.errors.is_empty().to_string(),
+            csv_field(&self.errors.join("; ")),
+        ];
+
+        for (_, is_unit, value) in self.fields.iter() {
+            // Unit fields are split into columns for centimetres, the original value, and the
+            // original unit
+            let field_columns = match (is_unit, value) {
+                (false, None) => vec!["".to_string()],
+                (true, None) => vec!["".to_string(); 3],
+                (_, Some(NormalizedValue::Integer(num))) => vec![num.to_string()],
+                (
+                    _,
+                    Some(NormalizedValue::Length {
+                        hundredths_of_centimetre,
+                        value,
+                        unit,
+                    }),
+                ) => vec![
+                    hundredths_of_centimetre
+                        .map(format_centimetres)
+                        .unwrap_or_default(),
+                    value.to_string(),
+                    csv_field(unit),
+                ],
+                (false, Some(NormalizedValue::Text(text))) => vec![csv_field(text)],
+                (true, Some(NormalizedValue::Text(text))) => {
+                    vec!["".to_string(), csv_field(text), "".to_string()]
+                }
+            };
+
+            columns.extend(field_columns);
+        }
+
+        columns.join(",")
+    }
+}
+
+fn csv_header(schema: &Schema) -> String {
+    let mut columns: Vec<String> = RECORD_COLUMNS.iter().map(|c| c.to_string()).collect();
+
+    for rule in schema.fields.iter() {
+        columns.extend(rule.csv_columns().iter().map(|c| csv_field(c)));
+    }
+
+    columns.join(",")
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
         }
+    }
+    escaped.push('"');
 
-        let year = self.birth_year.as_ref().unwrap().parse::<i32>().unwrap();
+    escaped
+}
 
-        (1920..=2002).contains(&year)
+fn csv_field(s: &str) -> String {
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
     }
+}
+
+struct FieldRule {
+    key: String,
+    required: bool,
+    validator: Validator,
+}
 
-    pub fn issue_year_is_valid(&self) -> bool {
-        if self.issue_year.as_ref().unwrap().len() != 4 {
-            return false;
+impl FieldRule {
+    /// Returns the names of the CSV columns that the field is exported to.
+    fn csv_columns(&self) -> Vec<String> {
+        match self.validator {
+            Validator::UnitRange(_) => vec![
+                format!("{}_cm", self.key),
+                format!("{}_value", self.key),
+                format!("{}_unit", self.key),
+            ],
+            _ => vec![self.key.clone()],
         }
+    }
+}
 
-        let year = self.issue_year.as_ref().unwrap().parse::<i32>().unwrap();
+/// Describes the fields that a document may contain and how to validate them.
+///
+/// Schemas are written with one field per line, giving the key, whether the field is "required"
+/// or "optional", the validator kind, and the arguments of the validator. Blank lines and lines
+/// starting with "//" are ignored. The validator kinds are:
+///
+/// * `any` - accepts any value
+/// * `int MIN-MAX [digits=N]` - an integer within a range, optionally with exactly N digits
+/// * `regex PATTERN` - a value matching the regular expression
+/// * `enum VALUE...` - one of the given values
+/// * `unit MIN-MAXUNIT...` - an integer followed by a unit, ex. `unit 150-193cm 59-76in`
+struct Schema {
+    fields: Vec<FieldRule>,
+}
 
-        (2010..=2020).contains(&year)
+impl Schema {
+    fn default() -> Schema {
+        Schema::from_str(DEFAULT_SCHEMA).unwrap()
     }
 
-    pub fn expiration_year_is_valid(&self) -> bool {
-        if self.expiration_year.as_ref().unwrap().len() != 4 {
-            return false;
+    fn from_str(schema_str: &str) -> Result<Schema, String> {
+        let mut fields: Vec<FieldRule> = vec![];
+        for (i, line) in schema_str.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+
+            let (parts, arguments) = split_leading_tokens(line, 3);
+            if parts.len() < 3 {
+                return Err(format!("Invalid schema line {}: {}", i + 1, line));
+            }
+
+            let key = parts[0].to_string();
+            let required = match parts[1] {
+                "required" => true,
+                "optional" => false,
+                r => {
+                    return Err(format!(
+                        "Expected \"required\" or \"optional\" on schema line {}: {}",
+                        i + 1,
+                        r
+                    ))
+                }
+            };
+            let validator = Validator::from_str(parts[2], arguments)
+                .map_err(|msg| format!("{} on schema line {}", msg, i + 1))?;
+
+            if RECORD_COLUMNS.contains(&key.as_str()) {
+                return Err(format!(
+                    "Reserved field name on schema line {}: {}",
+                    i + 1,
+                    key
+                ));
+            }
+
+            if fields.iter().any(|f| f.key == key) {
+                return Err(format!("Duplicate field on schema line {}: {}", i + 1, key));
+            }
+
+            let rule = FieldRule {
+                key,
+                required,
+                validator,
+            };
+
+            let columns = rule.csv_columns();
+            if let Some(other) = fields
+                .iter()
+                .find(|f| f.csv_columns().iter().any(|c| columns.contains(c)))
+            {
+                return Err(format!(
+                    "Field on schema line {} has the same export columns as field {}: {}",
+                    i + 1,
+                    other.key,
+                    rule.key
+                ));
+            }
+
+            fields.push(rule);
         }
 
-        let year = self
-            .expiration_year
-            .as_ref()
-            .unwrap()
-            .parse::<i32>()
-            .unwrap();
+        Ok(Schema { fields })
+    }
+}
 
-        (2020..=2030).contains(&year)
+/// Splits up to the given number of whitespace separated tokens off the front of a line, returning
+/// them along with the rest of the line with its surrounding whitespace trimmed.
+fn split_leading_tokens(line: &str, num_tokens: usize) -> (Vec<&str>, &str) {
+    let mut tokens = vec![];
+    let mut rest = line.trim_start();
+    while tokens.len() < num_tokens && !rest.is_empty() {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        tokens.push(&rest[..end]);
+        rest = rest[end..].trim_start();
     }
 
-    pub fn height_is_valid(&self) -> bool {
-        let height_str = self.height.as_ref().unwrap();
+    (tokens, rest.trim_end())
+}
 
-        let height_regex = self::regex::Regex::new(r"(\d+)(cm|in)$").unwrap();
+struct Passport {
+    /// Line number that the passport starts on in the input, starting from 1.
+    start_line: usize,
+    fields: BTreeMap<String, String>,
+    /// Fields whose keys are not in the schema, only kept when parsing leniently.
+    extras: BTreeMap<String, String>,
+    /// Problems that were skipped over when parsing leniently.
+    warnings: Vec<ParseError>,
+}
 
-        match height_regex.captures(height_str) {
-            None => false,
-            Some(capture) => {
-                let value: i32 = capture.get(1).unwrap().as_str().parse().unwrap();
-                let unit = capture.get(2).unwrap().as_str();
+impl Passport {
+    pub fn from_lines(
+        record: usize,
+        start_line: usize,
+        lines: &[String],
+        schema: &Schema,
+        mode: ParseMode,
+    ) -> Result<Passport, ParseError> {
+        let mut fields: BTreeMap<String, String> = BTreeMap::new();
+        let mut extras: BTreeMap<String, String> = BTreeMap::new();
+        let mut warnings: Vec<ParseError> = vec![];
+
+        for (i, line) in lines.iter().enumerate() {
+            for token in line.split_whitespace() {
+                let error = |reason: String| ParseError {
+                    record,
+                    line: start_line + i,
+                    token: token.to_string(),
+                    reason,
+                };
+
+                let key_value: Vec<&str> = token.split(':').collect();
+                if key_value.len() != 2 || key_value[0].is_empty() {
+                    match mode {
+                        ParseMode::Strict => {
+                            return Err(error("Field is not of the form key:value".to_string()))
+                        }
+                        ParseMode::Lenient => {
+                            warnings.push(error("Skipped malformed field".to_string()));
+                            continue;
+                        }
+                    }
+                }
 
-                assert!(unit == "cm" || unit == "in");
+                let key = key_value[0];
+                let value = key_value[1];
 
-                if unit == "cm" {
-                    (150..=193).contains(&value)
+                let destination = if schema.fields.iter().any(|f| f.key == key) {
+                    &mut fields
                 } else {
-                    (59..=76).contains(&value)
+                    match mode {
+                        ParseMode::Strict => return Err(error(format!("Unknown field {}", key))),
+                        ParseMode::Lenient => &mut extras,
+                    }
+                };
+
+                if let Some(previous) = destination.insert(key.to_string(), value.to_string()) {
+                    match mode {
+                        ParseMode::Strict => return Err(error(format!("Duplicate field {}", key))),
+                        ParseMode::Lenient => warnings.push(error(format!(
+                            "Duplicate field {} replaced previous value {}",
+                            key, previous
+                        ))),
+                    }
                 }
             }
         }
+
+        Ok(Passport {
+            start_line,
+            fields,
+            extras,
+            warnings,
+        })
+    }
+
+    pub fn has_required_fields(&self, schema: &Schema) -> bool {
+        schema
+            .fields
+            .iter()
+            .filter(|f| f.required)
+            .all(|f| self.fields.contains_key(&f.key))
+    }
+
+    pub fn is_valid(&self, schema: &Schema) -> bool {
+        self.check_fields(schema)
+            .iter()
+            .all(|(rule, status)| status.passes(rule))
+    }
+
+    /// Returns the status of each field in the schema for this passport.
+    fn check_fields<'a>(&self, schema: &'a Schema) -> Vec<(&'a FieldRule, FieldStatus)> {
+        schema
+            .fields
+            .iter()
+            .map(|rule| {
+                let status = match self.fields.get(&rule.key) {
+                    None => FieldStatus::Missing,
+                    Some(value) => match rule.validator.validate(&rule.key, value) {
+                        Ok(()) => FieldStatus::Valid,
+                        Err(reason) => FieldStatus::Invalid(reason),
+                    },
+                };
+
+                (rule, status)
+            })
+            .collect()
+    }
+
+    /// Returns the value of the field with the given key, including fields that are not in the
+    /// schema.
+    fn any_field(&self, key: &str) -> Option<&String> {
+        self.fields.get(key).or_else(|| self.extras.get(key))
+    }
+
+    /// Returns the keys and values of all of the fields, including fields that are not in the
+    /// schema, sorted by key.
+    fn all_fields(&self) -> Vec<(&str, &str)> {
+        let mut all_fields: Vec<(&str, &str)> = self
+            .fields
+            .iter()
+            .chain(self.extras.iter())
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        all_fields.sort_unstable();
+
+        all_fields
+    }
+}
+
+/// A passport along with the batch it was read from.
+struct BatchPassport {
+    batch: String,
+    /// Index of the passport in its batch, starting from 1.
+    record: usize,
+    passport: Passport,
+}
+
+impl fmt::Display for BatchPassport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} passport {} (line {})",
+            self.batch, self.record, self.passport.start_line
+        )
+    }
+}
+
+/// Reads the schema and the passports from each of the given batch files, printing any parsing
+/// warnings. Like `load`, this reads every passport before returning.
+fn load_batches(
+    options: &InputOptions,
+    batch_paths: &[&str],
+) -> Result<Vec<BatchPassport>, String> {
+    if batch_paths.is_empty() {
+        return Err("At least one batch file must be given".to_string());
+    }
+
+    let schema = read_schema(options.schema_path)?;
+
+    let mut passports: Vec<BatchPassport> = vec![];
+    for path in batch_paths.iter() {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) => return Err(format!("Unable to read batch file {}: {}", path, err)),
+        };
+
+        let batch = read_input(BufReader::new(file), &schema, options.parse_mode)
+            .map_err(|err| format!("{}: {}", path, err))?;
+
+        for (i, passport) in batch.into_iter().enumerate() {
+            for warning in passport.warnings.iter() {
+                eprintln!("Warning: {}: {}", path, warning);
+            }
+
+            passports.push(BatchPassport {
+                batch: path.to_string(),
+                record: i + 1,
+                passport,
+            });
+        }
     }
 
-    pub fn hair_color_is_valid(&self) -> bool {
-        let hair_color_str = self.hair_color.as_ref().unwrap();
-        let hair_color_regex = self::regex::Regex::new(r"^#[0-9,a-f]{6}$").unwrap();
+    Ok(passports)
+}
+
+fn read_schema(schema_path: Option<&str>) -> Result<Schema, String> {
+    match schema_path {
+        None => Ok(Schema::default()),
+        Some(path) => match fs::read_to_string(path) {
+            Ok(schema_str) => Schema::from_str(&schema_str),
+            Err(err) => Err(format!("Unable to read schema file {}: {}", path, err)),
+        },
+    }
+}
 
-        hair_color_regex.is_match(hair_color_str)
+/// Reads the schema and the passports from standard input, printing any parsing warnings.
+///
+/// Unlike `validate_stream`, which the puzzle parts use, this reads every passport before
+/// returning. The modes that use it need the whole input, either to compare passports against each
+/// other or to summarize them, or to avoid printing partial output before a parsing error.
+fn load(options: &InputOptions) -> Result<(Schema, Vec<Passport>), String> {
+    let schema = read_schema(options.schema_path)?;
+    let passports = read_input(io::stdin().lock(), &schema, options.parse_mode)
+        .map_err(|err| err.to_string())?;
+
+    for passport in passports.iter() {
+        for warning in passport.warnings.iter() {
+            eprintln!("Warning: {}", warning);
+        }
     }
 
-    pub fn eye_color_is_valid(&self) -> bool {
-        let eye_color_str = self.eye_color.as_ref().unwrap();
-        let eye_color_regex = self::regex::Regex::new(r"^(amb|blu|brn|gry|grn|hzl|oth)$").unwrap();
+    Ok((schema, passports))
+}
+
+/// Reads the schema and validates the passports from standard input as they are read.
+fn validate_input(options: &InputOptions) -> Result<ValidationCounts, String> {
+    let schema = read_schema(options.schema_path)?;
+
+    validate_stream(
+        io::stdin().lock(),
+        &schema,
+        options.parse_mode,
+        options.num_threads,
+        |warning| eprintln!("Warning: {}", warning),
+    )
+    .map_err(|err| err.to_string())
+}
+
+/// Iterator over the lines of each passport in the input, along with the line number that each
+/// passport starts on. Passports are read lazily, so the whole input does not need to fit in
+/// memory.
+struct PassportLines<R> {
+    lines: io::Lines<R>,
+    line_number: usize,
+}
 
-        eye_color_regex.is_match(eye_color_str)
+impl<R: BufRead> PassportLines<R> {
+    fn new(reader: R) -> PassportLines<R> {
+        PassportLines {
+            lines: reader.lines(),
+            line_number: 0,
+        }
     }
+}
+
+impl<R: BufRead> Iterator for PassportLines<R> {
+    type Item = (usize, Vec<String>);
 
-    pub fn passport_id_is_valid(&self) -> bool {
-        let passport_id_str = self.passport_id.as_ref().unwrap();
-        let passport_id_regex = self::regex::Regex::new(r"^[0-9]{9}$").unwrap();
+    fn next(&mut self) -> Option<(usize, Vec<String>)> {
+        let mut start_line = 0;
+        let mut lines: Vec<String> = vec![];
+        for line in &mut self.lines {
+            let line = line.unwrap();
+            self.line_number += 1;
+
+            if line.trim().is_empty() {
+                if !lines.is_empty() {
+                    return Some((start_line, lines));
+                }
+            } else {
+                if lines.is_empty() {
+                    start_line = self.line_number;
+                }
+                lines.push(line);
+            }
+        }
 
-        passport_id_regex.is_match(passport_id_str)
+        if lines.is_empty() {
+            None
+        } else {
+            Some((start_line, lines))
+        }
     }
 }
 
-fn read_input<R>(reader: R) -> Vec<Passport>
+fn read_input<R>(reader: R, schema: &Schema, mode: ParseMode) -> Result<Vec<Passport>, ParseError>
 where
     R: BufRead,
 {
-    let password_strings: Vec<Vec<String>> = reader
-        .lines()
-        .map(|l| l.unwrap())
-        .collect::<Vec<String>>()
-        .join("\n")
-        .split("\n\n")
-        .map(|lines| lines.split('\n').map(|l| l.to_string()).collect())
-        .collect();
+    PassportLines::new(reader)
+        .enumerate()
+        .map(|(i, (start_line, lines))| {
+            Passport::from_lines(i + 1, start_line, &lines, schema, mode)
+        })
+        .collect()
+}
+
+/// Parses and validates the passports from the given reader as they are read, passing each parsing
+/// warning to the given function in the order of the input.
+///
+/// Passports are read in batches of `BATCH_SIZE` and sent through a bounded channel to the given
+/// number of threads, so the threads validate batches while the next ones are still being read.
+/// Results can finish out of order, so they are held until the results of all of the earlier
+/// batches have been added.
+///
+/// If any passports fail to parse, the error for the earliest one is returned.
+fn validate_stream<R, W>(
+    reader: R,
+    schema: &Schema,
+    mode: ParseMode,
+    num_threads: usize,
+    mut on_warning: W,
+) -> Result<ValidationCounts, ParseError>
+where
+    R: BufRead,
+    W: FnMut(&ParseError),
+{
+    let num_threads = num_threads.max(1);
+    let (batch_sender, batch_receiver) =
+        mpsc::sync_channel::<(usize, PassportBatch)>(num_threads * 2);
+    let batch_receiver = Mutex::new(batch_receiver);
+    let (result_sender, result_receiver) = mpsc::channel::<(usize, BatchResult)>();
+
+    thread::scope(|scope| {
+        for _ in 0..num_threads {
+            let result_sender = result_sender.clone();
+            let batch_receiver = &batch_receiver;
+            scope.spawn(move || loop {
+                // Only hold the lock while waiting for a batch, so that the other threads can take
+                // batches while this one validates
+                let next_batch = batch_receiver.lock().unwrap().recv();
+                match next_batch {
+                    // Sending only fails once the earliest error has been returned
+                    Ok((index, batch)) => {
+                        let _ = result_sender.send((index, validate_batch(&batch, schema, mode)));
+                    }
+                    Err(_) => break,
+                }
+            });
+        }
+        drop(result_sender);
+
+        let mut results = OrderedResults::default();
+        let mut first_error: Option<ParseError> = None;
+        let mut passport_lines = PassportLines::new(reader).enumerate();
+        for index in 0.. {
+            let batch: PassportBatch = passport_lines
+                .by_ref()
+                .take(BATCH_SIZE)
+                .map(|(i, (start_line, lines))| (i + 1, start_line, lines))
+                .collect();
+            if batch.is_empty() {
+                break;
+            }
+
+            batch_sender.send((index, batch)).unwrap();
+            if let Err(err) = results.add_all(result_receiver.try_iter(), &mut on_warning) {
+                first_error = Some(err);
+                break;
+            }
+        }
+
+        // The threads stop once the batches run out, so this must happen before returning
+        drop(batch_sender);
+        if let Some(err) = first_error {
+            return Err(err);
+        }
 
-    password_strings
+        results.add_all(result_receiver.iter(), &mut on_warning)?;
+
+        Ok(results.counts)
+    })
+}
+
+/// The lines of each passport in a batch, along with its record number and the line it starts on.
+type PassportBatch = Vec<(usize, usize, Vec<String>)>;
+
+/// Counts of the passports in a batch along with their parsing warnings, or the first parsing
+/// error in the batch.
+type BatchResult = Result<(ValidationCounts, Vec<ParseError>), ParseError>;
+
+/// Combines the results of batches that may finish out of order, in the order of the batches.
+#[derive(Default)]
+struct OrderedResults {
+    counts: ValidationCounts,
+    /// Index of the next batch whose results can be added.
+    next_index: usize,
+    /// Results of batches that finished before an earlier batch.
+    pending: BTreeMap<usize, BatchResult>,
+}
+
+impl OrderedResults {
+    /// Adds the given results of batches by index, passing the warnings of each batch to the given
+    /// function once all of the earlier batches have been added. Returns the error of the earliest
+    /// batch that failed to parse, if any.
+    fn add_all<I, W>(&mut self, results: I, on_warning: &mut W) -> Result<(), ParseError>
+    where
+        I: Iterator<Item = (usize, BatchResult)>,
+        W: FnMut(&ParseError),
+    {
+        for (index, result) in results {
+            self.pending.insert(index, result);
+
+            while let Some(result) = self.pending.remove(&self.next_index) {
+                let (batch_counts, warnings) = result?;
+
+                for warning in warnings.iter() {
+                    on_warning(warning);
+                }
+                self.counts.add(&batch_counts);
+                self.next_index += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn validate_batch(
+    batch: &[(usize, usize, Vec<String>)],
+    schema: &Schema,
+    mode: ParseMode,
+) -> BatchResult {
+    let passports = batch
         .iter()
-        .map(|lines| Passport::from_lines(lines))
-        .collect::<Vec<Passport>>()
+        .map(|(record, start_line, lines)| {
+            Passport::from_lines(*record, *start_line, lines, schema, mode)
+        })
+        .collect::<Result<Vec<Passport>, ParseError>>()?;
+
+    let counts = ValidationCounts {
+        total: passports.len(),
+        with_required_fields: count_passports_with_required_fields(&passports, schema) as usize,
+        valid: count_valid_passports(&passports, schema) as usize,
+    };
+    let warnings = passports.into_iter().flat_map(|p| p.warnings).collect();
+
+    Ok((counts, warnings))
 }
 
-fn count_passports_with_required_fields(passports: &[Passport]) -> i32 {
-    passports.iter().filter(|p| p.has_required_fields()).count() as i32
+fn count_passports_with_required_fields(passports: &[Passport], schema: &Schema) -> i32 {
+    passports
+        .iter()
+        .filter(|p| p.has_required_fields(schema))
+        .count() as i32
+}
+
+fn count_valid_passports(passports: &[Passport], schema: &Schema) -> i32 {
+    passports.iter().filter(|p| p.is_valid(schema)).count() as i32
+}
+
+/// Groups the indices of the passports that share a passport ID, for each ID that is used by more
+/// than one passport.
+fn group_by_passport_id(passports: &[BatchPassport]) -> Vec<(String, Vec<usize>)> {
+    let mut groups: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (i, p) in passports.iter().enumerate() {
+        if let Some(passport_id) = p.passport.fields.get("pid") {
+            groups.entry(passport_id).or_default().push(i);
+        }
+    }
+
+    groups
+        .into_iter()
+        .filter(|(_, group)| group.len() > 1)
+        .map(|(passport_id, group)| (passport_id.to_string(), group))
+        .collect()
 }
 
-fn count_valid_passports(passports: &[Passport]) -> i32 {
-    passports.iter().filter(|p| p.is_valid()).count() as i32
+/// Returns each field that does not have the same value in all of the given passports, along with
+/// the value of the field in each passport.
+fn field_differences<'a>(passports: &[&'a Passport]) -> Vec<(String, Vec<Option<&'a str>>)> {
+    let keys: BTreeSet<&str> = passports
+        .iter()
+        .flat_map(|p| p.all_fields().into_iter().map(|(k, _)| k))
+        .collect();
+
+    keys.into_iter()
+        .filter_map(|key| {
+            let values: Vec<Option<&str>> = passports
+                .iter()
+                .map(|p| p.any_field(key).map(|v| v.as_str()))
+                .collect();
+
+            if values.iter().all(|v| *v == values[0]) {
+                None
+            } else {
+                Some((key.to_string(), values))
+            }
+        })
+        .collect()
+}
+
+/// Index of a passport along with the key of a field left out of it, if any.
+type FieldOmission<'a> = (usize, Option<&'a str>);
+
+/// Finds the pairs of passports that have the same fields and values except for exactly one field,
+/// which either has a different value or is only in one of the passports.
+///
+/// Each passport is indexed both by all of its fields and by its fields with each one left out, so
+/// two passports that differ only on some field end up sharing an index entry.
+fn find_near_duplicates(passports: &[BatchPassport]) -> Vec<(usize, usize, String)> {
+    // Maps the fields of each passport, possibly with one left out, to the passports and the key
+    // of the left out field
+    let mut index: HashMap<Vec<(&str, &str)>, Vec<FieldOmission>> = HashMap::new();
+    for (i, p) in passports.iter().enumerate() {
+        let fields = p.passport.all_fields();
+
+        for left_out in 0..fields.len() {
+            let mut partial_fields = fields.clone();
+            let (key, _) = partial_fields.remove(left_out);
+
+            index
+                .entry(partial_fields)
+                .or_default()
+                .push((i, Some(key)));
+        }
+
+        index.entry(fields).or_default().push((i, None));
+    }
+
+    let mut pairs: BTreeSet<(usize, usize, String)> = BTreeSet::new();
+    for entries in index.values() {
+        for (a, (a_index, a_key)) in entries.iter().enumerate() {
+            for (b_index, b_key) in entries[a + 1..].iter() {
+                let key = match (a_key, b_key) {
+                    // Both have the field, but with different values
+                    (Some(a_key), Some(b_key))
+                        if a_key == b_key
+                            && passports[*a_index].passport.any_field(a_key)
+                                != passports[*b_index].passport.any_field(b_key) =>
+                    {
+                        a_key
+                    }
+                    // Only one of them has the field
+                    (Some(key), None) | (None, Some(key)) => key,
+                    _ => continue,
+                };
+
+                pairs.insert((
+                    *a_index.min(b_index),
+                    *a_index.max(b_index),
+                    key.to_string(),
+                ));
+            }
+        }
+    }
+
+    pairs.into_iter().collect()
+}
+
+/// Describes the status of each field of each passport, followed by how many passports each rule
+/// rejected, with the rules that rejected the most passports first.
+fn validation_report(passports: &[Passport], schema: &Schema) -> String {
+    let mut report = String::new();
+    let mut rejections: BTreeMap<(String, &str), usize> = BTreeMap::new();
+    let mut num_valid = 0;
+
+    for (i, passport) in passports.iter().enumerate() {
+        let statuses = passport.check_fields(schema);
+        let is_valid = statuses.iter().all(|(rule, status)| status.passes(rule));
+        if is_valid {
+            num_valid += 1;
+        }
+
+        report.push_str(&format!(
+            "passport {} (line {}): {}\n",
+            i + 1,
+            passport.start_line,
+            if is_valid { "valid" } else { "invalid" }
+        ));
+
+        for (rule, status) in statuses.iter() {
+            let description = match status {
+                FieldStatus::Valid => "valid".to_string(),
+                FieldStatus::Missing if rule.required => {
+                    *rejections.entry((rule.key.clone(), "missing")).or_insert(0) += 1;
+                    "missing".to_string()
+                }
+                FieldStatus::Missing => "missing (optional)".to_string(),
+                FieldStatus::Invalid(reason) => {
+                    *rejections.entry((rule.key.clone(), "invalid")).or_insert(0) += 1;
+                    format!("invalid ({})", reason)
+                }
+            };
+
+            report.push_str(&format!("  {}: {}\n", rule.key, description));
+        }
+
+        for (key, value) in passport.extras.iter() {
+            report.push_str(&format!("  {}: unknown field ({})\n", key, value));
+        }
+    }
+
+    let mut rejections: Vec<((String, &str), usize)> = rejections.into_iter().collect();
+    rejections.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    report.push_str(&format!(
+        "\n{} of {} passports valid\n",
+        num_valid,
+        passports.len()
+    ));
+    report.push_str("rejections:\n");
+    for ((key, reason), count) in rejections.iter() {
+        report.push_str(&format!("  {} {}: {}\n", key, reason, count));
+    }
+
+    report
 }
 
 #[cfg(test)]
 mod tests {
+    extern crate test;
+
+    use self::test::Bencher;
     use super::*;
 
     #[test]
     fn validate_invalid_passport_01() {
         let input = b"eyr:1972 cid:100\nhcl:#18171d ecl:amb hgt:170 pid:186cm iyr:2018 byr:1926\n";
-        let passports = read_input(&input[..]);
+        let passports = read_input(&input[..], &Schema::default(), ParseMode::Strict).unwrap();
 
         let expected = 0;
-        let actual = count_valid_passports(&passports);
+        let actual = count_valid_passports(&passports, &Schema::default());
 
         assert_eq!(expected, actual)
     }
@@ -224,10 +1403,10 @@ mod tests {
     #[test]
     fn validate_invalid_passport_02() {
         let input = b"iyr:2019\nhcl:#602927 eyr:1967 hgt:170cm\necl:grn pid:012533040 byr:1946\n";
-        let passports = read_input(&input[..]);
+        let passports = read_input(&input[..], &Schema::default(), ParseMode::Strict).unwrap();
 
         let expected = 0;
-        let actual = count_valid_passports(&passports);
+        let actual = count_valid_passports(&passports, &Schema::default());
 
         assert_eq!(expected, actual)
     }
@@ -236,10 +1415,10 @@ mod tests {
     fn validate_invalid_passport_03() {
         let input =
             b"hcl:dab227 iyr:2012\necl:brn hgt:182cm pid:021572410 eyr:2020 byr:1992 cid:277\n";
-        let passports = read_input(&input[..]);
+        let passports = read_input(&input[..], &Schema::default(), ParseMode::Strict).unwrap();
 
         let expected = 0;
-        let actual = count_valid_passports(&passports);
+        let actual = count_valid_passports(&passports, &Schema::default());
 
         assert_eq!(expected, actual)
     }
@@ -247,10 +1426,10 @@ mod tests {
     #[test]
     fn validate_invalid_passport_04() {
         let input = b"hgt:59cm ecl:zzz\neyr:2038 hcl:74454a iyr:2023\npid:3556412378 byr:2007\n";
-        let passports = read_input(&input[..]);
+        let passports = read_input(&input[..], &Schema::default(), ParseMode::Strict).unwrap();
 
         let expected = 0;
-        let actual = count_valid_passports(&passports);
+        let actual = count_valid_passports(&passports, &Schema::default());
 
         assert_eq!(expected, actual)
     }
@@ -258,10 +1437,10 @@ mod tests {
     #[test]
     fn validate_valid_passport_01() {
         let input = b"pid:087499704 hgt:74in ecl:grn iyr:2012 eyr:2030 byr:1980\nhcl:#623a2f\n";
-        let passports = read_input(&input[..]);
+        let passports = read_input(&input[..], &Schema::default(), ParseMode::Strict).unwrap();
 
         let expected = 1;
-        let actual = count_valid_passports(&passports);
+        let actual = count_valid_passports(&passports, &Schema::default());
 
         assert_eq!(expected, actual)
     }
@@ -270,10 +1449,10 @@ mod tests {
     fn validate_valid_passport_02() {
         let input =
             b"eyr:2029 ecl:blu cid:129 byr:1989\niyr:2014 pid:896056539 hcl:#a97842 hgt:165cm\n";
-        let passports = read_input(&input[..]);
+        let passports = read_input(&input[..], &Schema::default(), ParseMode::Strict).unwrap();
 
         let expected = 1;
-        let actual = count_valid_passports(&passports);
+        let actual = count_valid_passports(&passports, &Schema::default());
 
         assert_eq!(expected, actual)
     }
@@ -282,10 +1461,10 @@ mod tests {
     fn validate_valid_passport_03() {
         let input =
             b"hcl:#888785\nhgt:164cm byr:2001 iyr:2015 cid:88\npid:545766238 ecl:hzl\neyr:2022\n";
-        let passports = read_input(&input[..]);
+        let passports = read_input(&input[..], &Schema::default(), ParseMode::Strict).unwrap();
 
         let expected = 1;
-        let actual = count_valid_passports(&passports);
+        let actual = count_valid_passports(&passports, &Schema::default());
 
         assert_eq!(expected, actual)
     }
@@ -293,11 +1472,461 @@ mod tests {
     #[test]
     fn validate_valid_passport_04() {
         let input = b"iyr:2010 hgt:158cm hcl:#b6652a ecl:blu byr:1944 eyr:2021 pid:093154719\n";
-        let passports = read_input(&input[..]);
+        let passports = read_input(&input[..], &Schema::default(), ParseMode::Strict).unwrap();
 
         let expected = 1;
-        let actual = count_valid_passports(&passports);
+        let actual = count_valid_passports(&passports, &Schema::default());
 
         assert_eq!(expected, actual)
     }
+
+    #[test]
+    fn schema_from_str() {
+        let schema = Schema::from_str(
+            "// document schema\nnum required int 1-10\n\ncolor optional enum red blue\nlen required unit 1-2m 100-200cm\ncode required regex ^[A-Z]{2} [0-9]+$",
+        )
+        .unwrap();
+
+        assert_eq!(4, schema.fields.len());
+        assert!(schema.fields[0].required);
+        assert!(!schema.fields[1].required);
+
+        assert!(schema.fields[0].validator.validate("k", "10").is_ok());
+        assert!(schema.fields[0].validator.validate("k", "11").is_err());
+        assert!(schema.fields[1].validator.validate("k", "blue").is_ok());
+        assert!(schema.fields[1].validator.validate("k", "green").is_err());
+        assert!(schema.fields[2].validator.validate("k", "2m").is_ok());
+        assert!(schema.fields[2].validator.validate("k", "150cm").is_ok());
+        assert!(schema.fields[2].validator.validate("k", "150").is_err());
+        assert!(schema.fields[3].validator.validate("k", "AB 12").is_ok());
+        assert!(schema.fields[3].validator.validate("k", "AB12").is_err());
+    }
+
+    #[test]
+    fn schema_from_str_extra_spaces() {
+        let schema = Schema::from_str("code  required\tregex   ^[A-Z]{2} [0-9]+$ ").unwrap();
+
+        assert!(schema.fields[0].required);
+        assert!(schema.fields[0].validator.validate("k", "AB 12").is_ok());
+        assert_eq!(
+            (vec!["a", "b"], "c  d"),
+            split_leading_tokens("  a b   c  d ", 2)
+        );
+        assert_eq!((vec!["a"], ""), split_leading_tokens("a ", 2));
+    }
+
+    #[test]
+    fn schema_from_str_invalid() {
+        assert!(Schema::from_str("num required").is_err());
+        assert!(Schema::from_str("num sometimes any").is_err());
+        assert!(Schema::from_str("num required int 1to10").is_err());
+        assert!(Schema::from_str("num required int 1-10 digits=x").is_err());
+        assert!(Schema::from_str("num required unit 1-10").is_err());
+        assert!(Schema::from_str("num required regex (").is_err());
+        assert!(Schema::from_str("num required lookup").is_err());
+        assert!(Schema::from_str("num required any\nnum optional any").is_err());
+        assert!(Schema::from_str("valid required any").is_err());
+        assert!(Schema::from_str("errors optional any").is_err());
+        assert!(Schema::from_str("len required unit 1-2m\nlen_cm optional any").is_err());
+        assert!(Schema::from_str("len_unit required any\nlen optional unit 1-2m").is_err());
+    }
+
+    #[test]
+    fn custom_schema_required_fields() {
+        let schema = Schema::from_str("name required any\nage optional int 0-150").unwrap();
+        let input = b"name:ann age:200\n\nage:30\n\nname:bob extra:1\n";
+        let passports = read_input(&input[..], &schema, ParseMode::Lenient).unwrap();
+
+        assert_eq!(2, count_passports_with_required_fields(&passports, &schema));
+        assert_eq!(1, count_valid_passports(&passports, &schema));
+    }
+
+    #[test]
+    fn validator_reasons() {
+        let schema = Schema::default();
+        let validate = |i: usize, value: &str| {
+            let rule = &schema.fields[i];
+            rule.validator.validate(&rule.key, value)
+        };
+
+        assert_eq!(Ok(()), validate(0, "2002"));
+        assert_eq!(
+            Err("byr 2003 exceeds 1920-2002".to_string()),
+            validate(0, "2003")
+        );
+        assert_eq!(
+            Err("byr 192 has 3 digits, expected 4".to_string()),
+            validate(0, "192")
+        );
+        assert_eq!(
+            Err("hgt 190in exceeds 59-76in".to_string()),
+            validate(3, "190in")
+        );
+        assert_eq!(
+            Err("hgt 190 does not have a unit of cm or in".to_string()),
+            validate(3, "190")
+        );
+        assert_eq!(
+            Err("ecl zzz is not one of amb, blu, brn, gry, grn, hzl, oth".to_string()),
+            validate(5, "zzz")
+        );
+    }
+
+    #[test]
+    fn validation_report_fields_and_rejections() {
+        let input = b"eyr:1972 cid:100\nhcl:#18171d ecl:amb hgt:170 pid:186cm iyr:2018 byr:1926\n\n\niyr:2010 hgt:158cm hcl:#b6652a ecl:blu byr:1944 eyr:2021 pid:093154719\n\nbyr:1990\n";
+        let passports = read_input(&input[..], &Schema::default(), ParseMode::Strict).unwrap();
+        let report = validation_report(&passports, &Schema::default());
+
+        assert_eq!(3, passports.len());
+        assert_eq!(
+            vec![1, 5, 7],
+            passports
+                .iter()
+                .map(|p| p.start_line)
+                .collect::<Vec<usize>>()
+        );
+
+        assert!(report.contains("passport 1 (line 1): invalid\n"));
+        assert!(report.contains("  eyr: invalid (eyr 1972 is below 2020-2030)\n"));
+        assert!(report.contains("  hgt: invalid (hgt 170 does not have a unit of cm or in)\n"));
+        assert!(report.contains("  cid: valid\n"));
+        assert!(report.contains("passport 2 (line 5): valid\n"));
+        assert!(report.contains("  cid: missing (optional)\n"));
+        assert!(report.contains("passport 3 (line 7): invalid\n"));
+        assert!(report.contains("  iyr: missing\n"));
+
+        assert!(report.contains("1 of 3 passports valid\n"));
+        assert!(report.contains("rejections:\n"));
+        assert!(report.contains("  eyr invalid: 1\n"));
+        assert!(report.contains("  iyr missing: 1\n"));
+    }
+
+    #[test]
+    fn read_input_strict_errors() {
+        let schema = Schema::default();
+        let read = |input: &str| read_input(input.as_bytes(), &schema, ParseMode::Strict);
+
+        assert_eq!(
+            Err(ParseError {
+                record: 2,
+                line: 4,
+                token: "abc:1".to_string(),
+                reason: "Unknown field abc".to_string(),
+            }),
+            read("byr:1990\n\niyr:2010\nhgt:150cm abc:1\n").map(|_| ())
+        );
+        assert_eq!(
+            Err(ParseError {
+                record: 1,
+                line: 2,
+                token: "byr:1991".to_string(),
+                reason: "Duplicate field byr".to_string(),
+            }),
+            read("byr:1990\nbyr:1991\n").map(|_| ())
+        );
+        assert_eq!(
+            Err(ParseError {
+                record: 1,
+                line: 1,
+                token: "hgt".to_string(),
+                reason: "Field is not of the form key:value".to_string(),
+            }),
+            read("hgt byr:1990\n").map(|_| ())
+        );
+    }
+
+    #[test]
+    fn validation_report_unknown_fields() {
+        let input = b"byr:1990 abc:1\n";
+        let schema = Schema::default();
+        let passports = read_input(&input[..], &schema, ParseMode::Lenient).unwrap();
+        let report = validation_report(&passports, &schema);
+
+        assert!(report.contains("  abc: unknown field (1)\n"));
+    }
+
+    #[test]
+    fn read_input_lenient_warnings() {
+        let input = b"byr:1990 abc:1 hgt\nbyr:1991 a:b:c\n";
+        let passports = read_input(&input[..], &Schema::default(), ParseMode::Lenient).unwrap();
+
+        assert_eq!(1, passports.len());
+
+        let passport = &passports[0];
+        assert_eq!(Some(&"1991".to_string()), passport.fields.get("byr"));
+        assert_eq!(Some(&"1".to_string()), passport.extras.get("abc"));
+        assert_eq!(
+            vec![
+                "Skipped malformed field: hgt".to_string(),
+                "Duplicate field byr replaced previous value 1990: byr:1991".to_string(),
+                "Skipped malformed field: a:b:c".to_string(),
+            ],
+            passport
+                .warnings
+                .iter()
+                .map(|w| format!("{}: {}", w.reason, w.token))
+                .collect::<Vec<String>>()
+        );
+        assert_eq!(
+            "Passport 1 (line 2): Skipped malformed field: a:b:c",
+            passport.warnings[2].to_string()
+        );
+    }
+
+    #[test]
+    fn normalized_record_to_json() {
+        let input = b"pid:087499704 hgt:74in ecl:grn iyr:2012 eyr:2030 byr:1980\nhcl:#623A2F\n";
+        let schema = Schema::default();
+        let passports = read_input(&input[..], &schema, ParseMode::Strict).unwrap();
+        let record = NormalizedRecord::from_passport(1, &passports[0], &schema);
+
+        let expected = "{\"record\":1,\"line\":1,\"valid\":false,\"errors\":[\"hcl #623A2F does not match ^#[0-9a-f]{6}$\"],\"byr\":1980,\"iyr\":2012,\"eyr\":2030,\"hgt\":{\"cm\":187.96,\"value\":74,\"unit\":\"in\"},\"hcl\":\"#623a2f\",\"ecl\":\"grn\",\"pid\":\"087499704\",\"cid\":null}";
+
+        assert_eq!(expected, record.to_json());
+    }
+
+    #[test]
+    fn normalized_value_lengths() {
+        let validator = Validator::from_str("unit", "1-100in 1-300cm 1-5ft").unwrap();
+        let centimetres = |value: &str| match NormalizedValue::from_value(&validator, value) {
+            NormalizedValue::Length {
+                hundredths_of_centimetre,
+                ..
+            } => hundredths_of_centimetre.map(format_centimetres),
+            other => panic!("Expected a length, got {:?}", other),
+        };
+
+        assert_eq!(Some("167.64".to_string()), centimetres("66in"));
+        assert_eq!(Some("185.42".to_string()), centimetres("73in"));
+        assert_eq!(Some("183.00".to_string()), centimetres("183cm"));
+        assert_eq!(None, centimetres("6ft"));
+        assert_eq!(
+            NormalizedValue::Text("2m".to_string()),
+            NormalizedValue::from_value(&validator, "2m")
+        );
+        assert_eq!("-0.05", format_centimetres(-5));
+    }
+
+    #[test]
+    fn normalized_record_to_csv_row() {
+        let input = b"hgt:170 ecl:a,b\"c byr:1980\n";
+        let schema = Schema::default();
+        let passports = read_input(&input[..], &schema, ParseMode::Strict).unwrap();
+        let record = NormalizedRecord::from_passport(3, &passports[0], &schema);
+
+        assert_eq!(
+            "record,line,valid,errors,byr,iyr,eyr,hgt_cm,hgt_value,hgt_unit,hcl,ecl,pid,cid",
+            csv_header(&schema)
+        );
+        assert_eq!(
+            "3,1,false,\"iyr missing; eyr missing; hgt 170 does not have a unit of cm or in; hcl missing; ecl a,b\"\"c is not one of amb, blu, brn, gry, grn, hzl, oth; pid missing\",1980,,,,170,,,\"a,b\"\"c\",,",
+            record.to_csv_row()
+        );
+    }
+
+    #[test]
+    fn json_string_escapes() {
+        assert_eq!("\"a\\\"b\\\\c\\n\\u0001\"", json_string("a\"b\\c\n\u{1}"));
+    }
+
+    #[test]
+    fn validate_stream_matches_counts() {
+        let input = b"eyr:1972 cid:100\nhcl:#18171d ecl:amb hgt:170 pid:186cm iyr:2018 byr:1926\n\niyr:2010 hgt:158cm hcl:#b6652a ecl:blu byr:1944 eyr:2021 pid:093154719\n\nbyr:1990\n";
+        let schema = Schema::default();
+
+        let expected = ValidationCounts {
+            total: 3,
+            with_required_fields: 2,
+            valid: 1,
+        };
+
+        for num_threads in 1..4 {
+            let actual =
+                validate_stream(&input[..], &schema, ParseMode::Strict, num_threads, |_| {});
+
+            assert_eq!(Ok(expected), actual);
+        }
+    }
+
+    #[test]
+    fn validate_stream_reports_earliest_error() {
+        let schema = Schema::default();
+        let input = generate_passports(5000).replace(" cid:1234\n", " bad:1234\n") + "bad:1\n";
+
+        let result = validate_stream(input.as_bytes(), &schema, ParseMode::Strict, 4, |_| {});
+
+        assert_eq!(1234, result.unwrap_err().record);
+    }
+
+    #[test]
+    fn validate_stream_warnings_in_order() {
+        let schema = Schema::default();
+        let input = generate_passports(5000).replace(" cid:", " cid::");
+
+        let mut records: Vec<usize> = vec![];
+        let result = validate_stream(input.as_bytes(), &schema, ParseMode::Lenient, 4, |w| {
+            records.push(w.record)
+        });
+
+        assert_eq!(5000, result.unwrap().total);
+        assert_eq!((1..=5000).collect::<Vec<usize>>(), records);
+    }
+
+    /// Generates a batch of passports where every third passport is invalid.
+    fn generate_passports(num_passports: usize) -> String {
+        let mut input = String::new();
+        for i in 1..=num_passports {
+            let byr = if i % 3 == 0 { 1900 } else { 1980 };
+            input.push_str(&format!(
+                "pid:{:09} hgt:74in ecl:grn iyr:2012 eyr:2030 byr:{}\nhcl:#623a2f cid:{}\n\n",
+                i, byr, i
+            ));
+        }
+
+        input
+    }
+
+    /// Validates generated passports on the given number of threads. Comparing the thread counts
+    /// shows how validation scales, which is only possible with as many cores as threads.
+    fn bench_validate_stream(b: &mut Bencher, num_threads: usize) {
+        let schema = Schema::default();
+        let input = generate_passports(10_000);
+
+        b.bytes = input.len() as u64;
+        b.iter(|| {
+            validate_stream(
+                input.as_bytes(),
+                &schema,
+                ParseMode::Strict,
+                num_threads,
+                |_| {},
+            )
+            .unwrap()
+        });
+    }
+
+    #[bench]
+    fn bench_validate_stream_one_thread(b: &mut Bencher) {
+        bench_validate_stream(b, 1);
+    }
+
+    #[bench]
+    fn bench_validate_stream_two_threads(b: &mut Bencher) {
+        bench_validate_stream(b, 2);
+    }
+
+    #[bench]
+    fn bench_validate_stream_four_threads(b: &mut Bencher) {
+        bench_validate_stream(b, 4);
+    }
+
+    fn batch_passports(batches: &[(&str, &str)], mode: ParseMode) -> Vec<BatchPassport> {
+        let schema = Schema::default();
+
+        batches
+            .iter()
+            .flat_map(|(batch, input)| {
+                read_input(input.as_bytes(), &schema, mode)
+                    .unwrap()
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(i, passport)| BatchPassport {
+                        batch: batch.to_string(),
+                        record: i + 1,
+                        passport,
+                    })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn duplicates_by_passport_id() {
+        let passports = batch_passports(
+            &[
+                ("a", "pid:1 hgt:170cm byr:1980\n\npid:2 hgt:150cm\n"),
+                ("b", "pid:3\n\n\npid:1 hgt:171cm ecl:blu byr:1980\n"),
+            ],
+            ParseMode::Strict,
+        );
+
+        let groups = group_by_passport_id(&passports);
+
+        assert_eq!(vec![("1".to_string(), vec![0, 3])], groups);
+        assert_eq!("b passport 2 (line 4)", passports[3].to_string());
+
+        let group_passports: Vec<&Passport> = groups[0]
+            .1
+            .iter()
+            .map(|i| &passports[*i].passport)
+            .collect();
+        assert_eq!(
+            vec![
+                ("ecl".to_string(), vec![None, Some("blu")]),
+                ("hgt".to_string(), vec![Some("170cm"), Some("171cm")]),
+            ],
+            field_differences(&group_passports)
+        );
+    }
+
+    #[test]
+    fn near_duplicates() {
+        let passports = batch_passports(
+            &[
+                (
+                    "a",
+                    "pid:1 hgt:170cm byr:1980\n\npid:2 hgt:170cm byr:1980\n",
+                ),
+                (
+                    "b",
+                    "pid:1 hgt:170cm\n\npid:1 hgt:170cm byr:1980\n\npid:3 hgt:171cm byr:1980\n",
+                ),
+            ],
+            ParseMode::Strict,
+        );
+
+        assert_eq!(
+            vec![
+                (0, 1, "pid".to_string()),
+                (0, 2, "byr".to_string()),
+                (1, 3, "pid".to_string()),
+                (2, 3, "byr".to_string()),
+            ],
+            find_near_duplicates(&passports)
+        );
+    }
+
+    #[test]
+    fn duplicates_compare_extras() {
+        let passports = batch_passports(
+            &[
+                (
+                    "a",
+                    "pid:1 hgt:170cm abc:1\n\npid:2 hgt:170cm abc:1 xyz:2\n",
+                ),
+                ("b", "pid:1 hgt:170cm abc:2\n"),
+            ],
+            ParseMode::Lenient,
+        );
+
+        let group_passports: Vec<&Passport> = vec![&passports[0].passport, &passports[2].passport];
+        assert_eq!(
+            vec![("abc".to_string(), vec![Some("1"), Some("2")])],
+            field_differences(&group_passports)
+        );
+        assert_eq!(
+            vec![(0, 2, "abc".to_string())],
+            find_near_duplicates(&passports)
+        );
+    }
+
+    #[test]
+    fn duplicates_without_batches() {
+        let options = InputOptions::default();
+
+        assert_eq!(
+            Err("At least one batch file must be given".to_string()),
+            load_batches(&options, &[]).map(|_| ())
+        );
+    }
 }
@@ -1,4 +1,5 @@
 #![feature(map_first_last)]
+#![cfg_attr(test, feature(test))]
 
 pub mod eight;
 pub mod eight_b;
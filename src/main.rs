@@ -1,5 +1,7 @@
 extern crate clap;
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use std::str::FromStr;
 
 extern crate advent_of_code_2020;
 
@@ -9,12 +11,14 @@ fn main() {
     let days = get_days();
     let app = days.iter().map(|d| d.0.clone()).fold(a, |b, day| {
         b.subcommand(
-            SubCommand::with_name(&format!("day{}", day)).arg(
-                Arg::with_name("part")
-                    .help("Selects the part to run (one, two)")
-                    .required(true)
-                    .index(1),
-            ),
+            SubCommand::with_name(&format!("day{}", day))
+                .arg(
+                    Arg::with_name("part")
+                        .help("Selects the part to run (one, two)")
+                        .required(true)
+                        .index(1),
+                )
+                .args(&get_day_args(&day)),
         )
     });
     let matches = app.get_matches();
@@ -25,6 +29,10 @@ fn main() {
         if let Some(matches) = matches.subcommand_matches(day_string) {
             let part = matches.value_of("part").unwrap();
 
+            if run_day_mode(&day, part, matches) {
+                continue;
+            }
+
             match part {
                 "one" => part_one(),
                 "two" => part_two(),
@@ -130,3 +138,414 @@ fn get_days() -> Vec<AdventOfCodeDay> {
         ),
     ]
 }
+
+/// Returns the additional arguments accepted by the given day, beyond the part to run.
+fn get_day_args(day: &str) -> Vec<Arg<'static, 'static>> {
+    match day {
+        "3" => vec![
+            Arg::with_name("slope")
+                .long("slope")
+                .help("Slope of the path to walk, as x_shift,y_shift")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .validator(validate_pair::<i32>),
+            Arg::with_name("start")
+                .long("start")
+                .help("Starting position of the path to walk, as x,y")
+                .takes_value(true)
+                .validator(validate_pair::<usize>),
+            Arg::with_name("wrap-vertically")
+                .long("wrap-vertically")
+                .help("Continues the path at the top of the map once it leaves the bottom"),
+            Arg::with_name("max-steps")
+                .long("max-steps")
+                .help("Maximum number of steps to take along the path")
+                .takes_value(true)
+                .validator(validate_value::<usize>),
+            Arg::with_name("legend")
+                .long("legend")
+                .help(
+                    "File listing the terrain types of the map, one \"symbol name cost\" per line",
+                )
+                .takes_value(true),
+            Arg::with_name("render")
+                .long("render")
+                .help("Prints the map with the walked path marked on it")
+                .takes_value(true)
+                .possible_values(&["text", "ppm"]),
+            Arg::with_name("x-shifts")
+                .long("x-shifts")
+                .help("Inclusive bounds of the slope x shifts to search, as min,max")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .validator(validate_pair::<i32>),
+            Arg::with_name("y-shifts")
+                .long("y-shifts")
+                .help("Inclusive bounds of the slope y shifts to search, as min,max")
+                .takes_value(true)
+                .validator(validate_pair::<i32>),
+        ],
+        "4" => vec![
+            Arg::with_name("schema")
+                .long("schema")
+                .help("File describing the passport fields and how to validate them")
+                .takes_value(true),
+            Arg::with_name("lenient").long("lenient").help(
+                "Skips over unknown, duplicate, and malformed passport fields with a warning",
+            ),
+            Arg::with_name("threads")
+                .long("threads")
+                .help("Number of threads to validate passports on")
+                .takes_value(true)
+                .validator(validate_value::<usize>),
+            Arg::with_name("batches")
+                .help("Passport batch files to search for duplicates")
+                .multiple(true)
+                .index(2),
+            Arg::with_name("format")
+                .long("format")
+                .help("Format to export the normalized passports in")
+                .takes_value(true)
+                .possible_values(&["jsonl", "csv"]),
+        ],
+        "5" => vec![
+            Arg::with_name("plane")
+                .long("plane")
+                .help("Number of row bits and column bits of the plane, as rows,columns")
+                .takes_value(true)
+                .validator(validate_pair::<u32>),
+            Arg::with_name("letters")
+                .long("letters")
+                .help("Boarding pass letters for the front, back, left, and right halves")
+                .takes_value(true),
+            Arg::with_name("groups")
+                .long("groups")
+                .help("Sizes of the groups of passengers to allocate seats to, separated by commas")
+                .takes_value(true)
+                .validator(validate_list::<u32>),
+            Arg::with_name("block")
+                .long("block")
+                .help(
+                    "Rule for blocking seats from allocation, as empty-middle or columns=c1,c2,...",
+                )
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(validate_value::<advent_of_code_2020::five::BlockingRule>),
+        ],
+        "6" => vec![
+            Arg::with_name("alphabet")
+                .long("alphabet")
+                .help("Characters of the questions that can be answered, in order")
+                .takes_value(true)
+                .validator(validate_value::<advent_of_code_2020::six::Alphabet>),
+            Arg::with_name("min-members")
+                .long("min-members")
+                .help("Number of members of a group that must have answered yes to a question")
+                .takes_value(true)
+                .validator(validate_positive),
+            Arg::with_name("exact")
+                .long("exact")
+                .help("Only count questions answered yes by exactly the minimum number of members"),
+            Arg::with_name("threshold")
+                .long("threshold")
+                .help("Similarity at or above which groups are clustered together, from 0 to 1")
+                .takes_value(true)
+                .validator(validate_fraction),
+            Arg::with_name("top")
+                .long("top")
+                .help("Number of most similar and most dissimilar pairs of groups to list")
+                .takes_value(true)
+                .validator(validate_value::<usize>),
+        ],
+        "7" => vec![
+            Arg::with_name("bag")
+                .long("bag")
+                .help("Color of the bag to query, such as \"shiny gold\"")
+                .takes_value(true),
+            Arg::with_name("outer")
+                .long("outer")
+                .help("Color of the outermost bag of the nesting chains to explain")
+                .takes_value(true),
+            Arg::with_name("all")
+                .long("all")
+                .help("Explain every nesting chain rather than only the shortest one"),
+            Arg::with_name("top")
+                .long("top")
+                .help("Number of colors that make up the most bags to list")
+                .takes_value(true)
+                .validator(validate_value::<usize>),
+            Arg::with_name("query")
+                .long("query")
+                .help("Query for the bags to find, such as: directly contains >= 3 \"shiny gold\"")
+                .takes_value(true),
+            Arg::with_name("rules")
+                .long("rules")
+                .help("File of bag rules to run interactive commands against")
+                .takes_value(true),
+            Arg::with_name("root")
+                .long("root")
+                .help("Color of the bag to limit the exported graph to the contents of")
+                .takes_value(true),
+            Arg::with_name("highlight-ancestors")
+                .long("highlight-ancestors")
+                .help("Color of the bag to highlight in the exported graph, along with its ancestors")
+                .takes_value(true)
+                .conflicts_with("highlight-descendants"),
+            Arg::with_name("highlight-descendants")
+                .long("highlight-descendants")
+                .help("Color of the bag to highlight in the exported graph, along with its descendants")
+                .takes_value(true),
+        ],
+        _ => vec![],
+    }
+}
+
+/// Runs the given part if it is one of the additional modes supported by the given day. Returns
+/// false if the day has no such mode.
+fn run_day_mode(day: &str, part: &str, matches: &ArgMatches) -> bool {
+    match (day, part) {
+        ("3", "walk") => {
+            let (x_shift, y_shift) = parse_pair(matches.value_of("slope").unwrap_or("3,1"));
+            let (x, y) = parse_pair(matches.value_of("start").unwrap_or("0,0"));
+
+            let mut options = advent_of_code_2020::three::PathOptions::new(
+                advent_of_code_2020::three::Position { x, y },
+            );
+            options.wrap_vertically = matches.is_present("wrap-vertically");
+            options.max_steps = matches.value_of("max-steps").map(|s| s.parse().unwrap());
+
+            let render = matches.value_of("render").map(|s| s.parse().unwrap());
+
+            advent_of_code_2020::three::walk(
+                &advent_of_code_2020::three::PositionChange { x_shift, y_shift },
+                &options,
+                matches.value_of("legend"),
+                render,
+            );
+        }
+        ("3", "search") => {
+            let x_shift_bounds = parse_pair(matches.value_of("x-shifts").unwrap_or("0,10"));
+            let y_shift_bounds = parse_pair(matches.value_of("y-shifts").unwrap_or("1,5"));
+
+            advent_of_code_2020::three::search(
+                x_shift_bounds,
+                y_shift_bounds,
+                matches.value_of("legend"),
+            );
+        }
+        ("4", mode) if mode != "duplicates" && matches.is_present("batches") => {
+            println!("Batch files can only be given in duplicates mode")
+        }
+        ("4", "one") => {
+            advent_of_code_2020::four::part_one_with_options(&get_day_four_options(matches))
+        }
+        ("4", "two") => {
+            advent_of_code_2020::four::part_two_with_options(&get_day_four_options(matches))
+        }
+        ("4", "report") => advent_of_code_2020::four::report(&get_day_four_options(matches)),
+        ("4", "duplicates") => {
+            let batch_paths: Vec<&str> =
+                matches.values_of("batches").map_or(vec![], |v| v.collect());
+
+            advent_of_code_2020::four::duplicates(&get_day_four_options(matches), &batch_paths);
+        }
+        ("4", "export") => advent_of_code_2020::four::export(
+            &get_day_four_options(matches),
+            matches
+                .value_of("format")
+                .unwrap_or("jsonl")
+                .parse()
+                .unwrap(),
+        ),
+        ("5", "one") => match get_seat_plane(matches) {
+            Ok(plane) => advent_of_code_2020::five::part_one_with_plane(&plane),
+            Err(msg) => println!("{}", msg),
+        },
+        ("5", "two") => match get_seat_plane(matches) {
+            Ok(plane) => advent_of_code_2020::five::part_two_with_plane(&plane),
+            Err(msg) => println!("{}", msg),
+        },
+        ("5", "map") => match get_seat_plane(matches) {
+            Ok(plane) => advent_of_code_2020::five::seat_map(&plane),
+            Err(msg) => println!("{}", msg),
+        },
+        ("5", "encode") => match get_seat_plane(matches) {
+            Ok(plane) => advent_of_code_2020::five::encode(&plane),
+            Err(msg) => println!("{}", msg),
+        },
+        ("5", "allocate") => match get_seat_plane(matches) {
+            Ok(plane) => {
+                let group_sizes = parse_list(matches.value_of("groups").unwrap_or(""));
+                let rules = matches
+                    .values_of("block")
+                    .map_or(vec![], |v| v.map(|r| r.parse().unwrap()).collect());
+
+                advent_of_code_2020::five::allocate(&plane, &group_sizes, rules);
+            }
+            Err(msg) => println!("{}", msg),
+        },
+        ("6", "one") => advent_of_code_2020::six::part_one_with_alphabet(&get_alphabet(matches)),
+        ("6", "two") => advent_of_code_2020::six::part_two_with_alphabet(&get_alphabet(matches)),
+        ("6", "quorum") => advent_of_code_2020::six::quorum(
+            &get_alphabet(matches),
+            matches
+                .value_of("min-members")
+                .map_or(1, |m| m.parse().unwrap()),
+            matches.is_present("exact"),
+        ),
+        ("6", "similarity") => advent_of_code_2020::six::similarity(
+            &get_alphabet(matches),
+            matches
+                .value_of("threshold")
+                .map_or(0.8, |t| t.parse().unwrap()),
+            matches.value_of("top").map_or(5, |t| t.parse().unwrap()),
+        ),
+        ("6", "histogram") => advent_of_code_2020::six::histogram(&get_alphabet(matches)),
+        ("7", "ancestors") => {
+            advent_of_code_2020::seven::ancestors(matches.value_of("bag").unwrap_or("shiny gold"))
+        }
+        ("7", "explain") => advent_of_code_2020::seven::explain(
+            matches.value_of("bag").unwrap_or("shiny gold"),
+            matches.value_of("outer"),
+            matches.is_present("all"),
+        ),
+        ("7", "dot") => {
+            let highlight = match (
+                matches.value_of("highlight-ancestors"),
+                matches.value_of("highlight-descendants"),
+            ) {
+                (Some(bag), _) => Some((
+                    bag.to_string(),
+                    advent_of_code_2020::seven::Relatives::Ancestors,
+                )),
+                (None, Some(bag)) => Some((
+                    bag.to_string(),
+                    advent_of_code_2020::seven::Relatives::Descendants,
+                )),
+                (None, None) => None,
+            };
+
+            advent_of_code_2020::seven::export_dot(&advent_of_code_2020::seven::DotOptions {
+                highlight,
+                root: matches.value_of("root").map(|r| r.to_string()),
+            });
+        }
+        ("7", "breakdown") => advent_of_code_2020::seven::breakdown(
+            matches.value_of("bag").unwrap_or("shiny gold"),
+            matches.value_of("top").map_or(10, |t| t.parse().unwrap()),
+        ),
+        ("7", "interactive") => advent_of_code_2020::seven::interactive(matches.value_of("rules")),
+        ("7", "query") => match matches.value_of("query") {
+            Some(query) => match query.parse() {
+                Ok(query) => advent_of_code_2020::seven::query(&query),
+                Err(msg) => println!("Invalid query: {}", msg),
+            },
+            None => println!("A query must be given with --query"),
+        },
+        ("7", "format") => advent_of_code_2020::seven::format(),
+        ("7", "validate") => advent_of_code_2020::seven::validate(),
+        _ => return false,
+    }
+
+    true
+}
+
+fn get_day_four_options<'a>(
+    matches: &'a ArgMatches,
+) -> advent_of_code_2020::four::InputOptions<'a> {
+    let mut options = advent_of_code_2020::four::InputOptions {
+        schema_path: matches.value_of("schema"),
+        parse_mode: if matches.is_present("lenient") {
+            advent_of_code_2020::four::ParseMode::Lenient
+        } else {
+            advent_of_code_2020::four::ParseMode::Strict
+        },
+        ..advent_of_code_2020::four::InputOptions::default()
+    };
+
+    if let Some(num_threads) = matches.value_of("threads") {
+        options.num_threads = num_threads.parse().unwrap();
+    }
+
+    options
+}
+
+fn get_seat_plane(matches: &ArgMatches) -> Result<advent_of_code_2020::five::SeatPlane, String> {
+    let (row_bits, column_bits) = parse_pair(matches.value_of("plane").unwrap_or("7,3"));
+    let letters = matches.value_of("letters").unwrap_or("FBLR");
+
+    advent_of_code_2020::five::SeatPlane::new(row_bits, column_bits, letters)
+}
+
+fn get_alphabet(matches: &ArgMatches) -> advent_of_code_2020::six::Alphabet {
+    matches
+        .value_of("alphabet")
+        .map_or_else(Default::default, |a| a.parse().unwrap())
+}
+
+fn validate_value<T: FromStr>(value: String) -> Result<(), String> {
+    match value.parse::<T>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("Invalid value: {}", value)),
+    }
+}
+
+fn validate_positive(value: String) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(num) if num > 0 => Ok(()),
+        _ => Err(format!("Expected a value of at least 1: {}", value)),
+    }
+}
+
+fn validate_fraction(value: String) -> Result<(), String> {
+    match value.parse::<f64>() {
+        Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(()),
+        _ => Err(format!("Expected a value from 0 to 1: {}", value)),
+    }
+}
+
+fn validate_pair<T: FromStr>(value: String) -> Result<(), String> {
+    let parts: Vec<&str> = value.split(',').collect();
+    if parts.len() == 2 && parts.iter().all(|p| p.parse::<T>().is_ok()) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Expected a pair of values separated by a comma: {}",
+            value
+        ))
+    }
+}
+
+fn validate_list<T: FromStr>(value: String) -> Result<(), String> {
+    if value.split(',').all(|p| p.parse::<T>().is_ok()) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Expected a list of values separated by commas: {}",
+            value
+        ))
+    }
+}
+
+/// Parses a list of values that has already been checked by `validate_list`.
+fn parse_list<T: FromStr>(value: &str) -> Vec<T> {
+    value
+        .split(',')
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.parse::<T>() {
+            Ok(v) => v,
+            Err(_) => panic!("Invalid list value: {}", value),
+        })
+        .collect()
+}
+
+/// Parses a pair of values that has already been checked by `validate_pair`.
+fn parse_pair<T: FromStr>(value: &str) -> (T, T) {
+    let mut parts = value.split(',').map(|p| match p.parse::<T>() {
+        Ok(v) => v,
+        Err(_) => panic!("Invalid pair value: {}", value),
+    });
+
+    (parts.next().unwrap(), parts.next().unwrap())
+}
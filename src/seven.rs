@@ -1,146 +1,1854 @@
-extern crate regex;
-
-use std::collections::{BTreeMap, BTreeSet};
-use std::io::{self, BufRead};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::str::FromStr;
 
 pub fn part_one() {
-    let rules = read_input(io::stdin().lock());
-    let answer = num_bags_can_contain(&rules, "shiny gold");
+    let answer = read_valid_input(io::stdin().lock())
+        .map(|rules| num_bags_can_contain(&rules, "shiny gold"));
 
-    println!("{}", answer);
+    match answer {
+        Ok(answer) => println!("{}", answer),
+        Err(msg) => println!("{}", msg),
+    }
 }
 
 pub fn part_two() {
-    let rules = read_input(io::stdin().lock());
-    let answer = num_bags_within(&rules, "shiny gold");
+    let answer = read_valid_input(io::stdin().lock())
+        .and_then(|rules| num_bags_within(&rules, "shiny gold"));
+
+    match answer {
+        Ok(answer) => println!("{}", answer),
+        Err(msg) => println!("{}", msg),
+    }
+}
+
+/// Prints every bag color that can eventually contain the given bag.
+pub fn ancestors(bag: &str) {
+    match read_valid_input(io::stdin().lock()) {
+        Ok(rules) => {
+            for ancestor in rules.ancestors(bag) {
+                println!("{}", ancestor);
+            }
+        }
+        Err(msg) => println!("{}", msg),
+    }
+}
+
+/// Prints the nesting chains from the outer bag down to the target bag, either the shortest one or
+/// all of them. If no outer bag is given, prints the shortest chain from every bag that can contain
+/// the target bag.
+pub fn explain(target_bag: &str, outer_bag: Option<&str>, all: bool) {
+    let rules = match read_valid_input(io::stdin().lock()) {
+        Ok(rules) => rules,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+
+    let outer_bags: Vec<String> = match outer_bag {
+        Some(outer_bag) => vec![outer_bag.to_string()],
+        None => rules.ancestors(target_bag).into_iter().collect(),
+    };
+
+    for outer_bag in outer_bags.iter() {
+        let chains = if all {
+            rules.all_chains(outer_bag, target_bag)
+        } else {
+            rules
+                .shortest_chain(outer_bag, target_bag)
+                .into_iter()
+                .collect()
+        };
+
+        if chains.is_empty() {
+            println!("{} bags cannot contain {} bags", outer_bag, target_bag);
+        }
+        for chain in chains.iter() {
+            println!("{}", chain);
+        }
+    }
+}
+
+/// Prints the bag rules as a GraphViz DOT graph. The rules do not need to be valid, so that cycles
+/// and undefined colors can be inspected.
+pub fn export_dot(options: &DotOptions) {
+    match read_input(io::stdin().lock()).and_then(|rules| rules.to_dot(options)) {
+        Ok(dot) => print!("{}", dot),
+        Err(msg) => println!("{}", msg),
+    }
+}
+
+/// Prints the bag rules in their canonical form, with one rule per line ordered by bag color.
+pub fn format() {
+    match read_input(io::stdin().lock()) {
+        Ok(rules) => print!("{}", rules),
+        Err(msg) => println!("{}", msg),
+    }
+}
+
+/// Prints the total number of bags within the given bag, followed by the colors that make up the
+/// most bags within it.
+pub fn breakdown(bag: &str, top: usize) {
+    let report = read_valid_input(io::stdin().lock())
+        .and_then(|rules| rules.breakdown(bag))
+        .and_then(|breakdown| format_breakdown(bag, &breakdown, top));
+
+    match report {
+        Ok(report) => print!("{}", report),
+        Err(msg) => println!("{}", msg),
+    }
+}
+
+fn format_breakdown(bag: &str, breakdown: &BagBreakdown, top: usize) -> Result<String, String> {
+    let total = total_bags(bag, breakdown)?;
+
+    // Bags held zero times make up none of the total, and would divide by zero when it is zero
+    let mut contributors: Vec<(&String, &u128)> =
+        breakdown.iter().filter(|(_, count)| **count > 0).collect();
+    contributors
+        .sort_by(|(a_bag, a_count), (b_bag, b_count)| b_count.cmp(a_count).then(a_bag.cmp(b_bag)));
+
+    let mut report = format!("{} bags contain {} bags\n", bag, total);
+    for (contributor, count) in contributors.iter().take(top) {
+        report.push_str(&format!(
+            "  {} {} ({:.1}%)\n",
+            count,
+            contributor,
+            **count as f64 / total as f64 * 100.0
+        ));
+    }
+
+    Ok(report)
+}
+
+/// Runs commands from standard input against the bag rules in the given file, or against no rules
+/// if no file is given. Supported commands are "set <rule>", "del <color>", "contains <color>",
+/// and "within <color>".
+pub fn interactive(rules_path: Option<&str>) {
+    let rules = match rules_path {
+        Some(path) => File::open(path)
+            .map_err(|err| format!("Unable to read rules file {}: {}", path, err))
+            .and_then(|file| read_input(BufReader::new(file))),
+        None => Ok(BagRules::new(BTreeMap::new())),
+    };
+    let mut rules = match rules {
+        Ok(rules) => rules,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+
+    for line in io::stdin().lock().lines() {
+        let line = line.unwrap();
+        if !line.trim().is_empty() {
+            println!("{}", run_command(&mut rules, line.trim()));
+        }
+    }
+}
+
+fn run_command(rules: &mut BagRules, command: &str) -> String {
+    let (name, rest) = match command.find(' ') {
+        Some(i) => (&command[..i], &command[i..]),
+        None => (command, ""),
+    };
+    let argument = rest.trim();
+    // Number of characters before the argument, so that columns within it can be reported as
+    // columns within the command
+    let argument_offset = command.chars().count() - rest.trim_start().chars().count();
+
+    match name {
+        "set" => match RuleParser::new(argument).parse_rule() {
+            Ok((bag, contained_bags)) => {
+                let message = format!("Set rule for {} bags", bag);
+                rules.set_rule(&bag, contained_bags);
+                message
+            }
+            Err(err) => format!("Column {}: {}", err.column + argument_offset, err.message),
+        },
+        "del" => {
+            if rules.remove_rule(argument) {
+                format!("Removed rule for {} bags", argument)
+            } else {
+                format!("No rule for {} bags", argument)
+            }
+        }
+        "contains" => format!(
+            "{} bags can contain {} bags",
+            num_bags_can_contain(rules, argument),
+            argument
+        ),
+        "within" => match num_bags_within(rules, argument) {
+            Ok(num_bags) => format!("{} bags contain {} bags", argument, num_bags),
+            Err(msg) => msg,
+        },
+        _ => format!("Unknown command: {}", name),
+    }
+}
+
+/// Prints the color of every bag that matches the given query, one per line.
+pub fn query(query: &Query) {
+    let matching = read_valid_input(io::stdin().lock()).and_then(|rules| rules.matching(query));
+
+    match matching {
+        Ok(matching) => {
+            for bag in matching {
+                println!("{}", bag);
+            }
+        }
+        Err(msg) => println!("{}", msg),
+    }
+}
+
+/// Prints each problem found in the bag rules on standard input.
+pub fn validate() {
+    let errors = match read_input(io::stdin().lock()) {
+        Ok(rules) => rules.validate(),
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+
+    if errors.is_empty() {
+        println!("Bag rules are valid");
+    }
+    for error in errors.iter() {
+        println!("{}", error);
+    }
+}
+
+/// A problem with a set of bag rules that keeps them from being counted.
+#[derive(Debug, PartialEq)]
+pub enum RuleError {
+    /// A bag contains a color that has no rule of its own.
+    UndefinedColor { bag: String, contained: String },
+    /// A chain of bags that each contain the next, where the last bag is the first bag.
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleError::UndefinedColor { bag, contained } => write!(
+                f,
+                "{} bags contain {} bags, which have no rule",
+                bag, contained
+            ),
+            RuleError::Cycle(path) => write!(f, "Bags contain themselves: {}", path.join(" -> ")),
+        }
+    }
+}
+
+/// A condition on bags, which can be parsed from text such as:
+///
+/// ```text
+/// directly contains >= 3 "shiny gold"
+/// depth > 4
+/// contains "shiny gold" and contains "faded blue"
+/// not (empty or within "dark olive")
+/// ```
+///
+/// Conditions can be combined with "and", "or", and "not", where "and" binds more tightly than
+/// "or", and grouped with parentheses. Bag colors are quoted.
+#[derive(Debug, PartialEq)]
+pub enum Query {
+    /// Bags that directly contain the given bag, optionally with the number of it they contain
+    /// compared against a value.
+    DirectlyContains(String, Option<(Comparison, u128)>),
+    /// Bags that eventually contain the given bag, optionally with the total number of it they
+    /// contain compared against a value.
+    Contains(String, Option<(Comparison, u128)>),
+    /// Bags that are eventually contained within the given bag.
+    Within(String),
+    /// Bags whose number of levels of bags nested inside them compares against a value. Bags that
+    /// contain no other bags have a depth of zero.
+    Depth(Comparison, u128),
+    /// Bags that contain no other bags.
+    Empty,
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+}
+
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(query_str: &str) -> Result<Query, String> {
+        let mut parser = QueryParser {
+            tokens: tokenize_query(query_str)?,
+            position: 0,
+            end_column: query_str.chars().count() + 1,
+        };
+
+        let query = parser.parse_or()?;
+        if parser.position < parser.tokens.len() {
+            return Err(parser.error("Unexpected text after the end of the query"));
+        }
+
+        Ok(query)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    fn matches(&self, a: u128, b: u128) -> bool {
+        match self {
+            Comparison::Less => a < b,
+            Comparison::LessOrEqual => a <= b,
+            Comparison::Equal => a == b,
+            Comparison::GreaterOrEqual => a >= b,
+            Comparison::Greater => a > b,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum QueryToken {
+    Word(String),
+    Color(String),
+    Number(u128),
+    Comparison(Comparison),
+    Open,
+    Close,
+}
+
+/// Splits a query into tokens, along with the column each token starts at.
+fn tokenize_query(query_str: &str) -> Result<Vec<(usize, QueryToken)>, String> {
+    let chars: Vec<char> = query_str.chars().collect();
+
+    let mut tokens = vec![];
+    let mut position = 0;
+    while position < chars.len() {
+        let start = position;
+        let c = chars[position];
+        let rest: String = chars[position..].iter().take(2).collect();
+
+        let token = if c.is_whitespace() {
+            position += 1;
+            continue;
+        } else if c == '(' {
+            position += 1;
+            QueryToken::Open
+        } else if c == ')' {
+            position += 1;
+            QueryToken::Close
+        } else if c == '"' {
+            let color: String = chars[position + 1..]
+                .iter()
+                .take_while(|c| **c != '"')
+                .collect();
+            position += color.chars().count() + 2;
+            if position > chars.len() {
+                return Err(format!("Column {}: Unterminated bag color", start + 1));
+            }
+            QueryToken::Color(color)
+        } else if let Some((op, comparison)) = [
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+            ("=", Comparison::Equal),
+        ]
+        .iter()
+        .find(|(op, _)| rest.starts_with(op))
+        {
+            position += op.len();
+            QueryToken::Comparison(*comparison)
+        } else if c.is_ascii_digit() {
+            let digits: String = chars[position..]
+                .iter()
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            position += digits.len();
+            QueryToken::Number(
+                digits
+                    .parse()
+                    .map_err(|_| format!("Column {}: Number is too large", start + 1))?,
+            )
+        } else if c.is_ascii_lowercase() {
+            let word: String = chars[position..]
+                .iter()
+                .take_while(|c| c.is_ascii_lowercase())
+                .collect();
+            position += word.len();
+            QueryToken::Word(word)
+        } else {
+            return Err(format!(
+                "Column {}: Unexpected character '{}'",
+                start + 1,
+                c
+            ));
+        };
+
+        tokens.push((start + 1, token));
+    }
+
+    Ok(tokens)
+}
+
+struct QueryParser {
+    tokens: Vec<(usize, QueryToken)>,
+    position: usize,
+    /// The column just after the end of the query, where errors about missing tokens are reported.
+    end_column: usize,
+}
+
+impl QueryParser {
+    fn parse_or(&mut self) -> Result<Query, String> {
+        let mut query = self.parse_and()?;
+        while self.try_consume_word("or") {
+            query = Query::Or(Box::new(query), Box::new(self.parse_and()?));
+        }
+
+        Ok(query)
+    }
+
+    fn parse_and(&mut self) -> Result<Query, String> {
+        let mut query = self.parse_not()?;
+        while self.try_consume_word("and") {
+            query = Query::And(Box::new(query), Box::new(self.parse_not()?));
+        }
 
-    println!("{}", answer);
+        Ok(query)
+    }
+
+    fn parse_not(&mut self) -> Result<Query, String> {
+        if self.try_consume_word("not") {
+            return Ok(Query::Not(Box::new(self.parse_not()?)));
+        }
+
+        if self.peek() == Some(&QueryToken::Open) {
+            self.position += 1;
+            let query = self.parse_or()?;
+            if self.peek() != Some(&QueryToken::Close) {
+                return Err(self.error("Expected \")\""));
+            }
+            self.position += 1;
+
+            return Ok(query);
+        }
+
+        self.parse_condition()
+    }
+
+    fn parse_condition(&mut self) -> Result<Query, String> {
+        if self.try_consume_word("directly") {
+            if !self.try_consume_word("contains") {
+                return Err(self.error("Expected \"contains\""));
+            }
+            let count = self.parse_optional_count()?;
+            Ok(Query::DirectlyContains(self.parse_color()?, count))
+        } else if self.try_consume_word("contains") {
+            let count = self.parse_optional_count()?;
+            Ok(Query::Contains(self.parse_color()?, count))
+        } else if self.try_consume_word("within") {
+            Ok(Query::Within(self.parse_color()?))
+        } else if self.try_consume_word("depth") {
+            match self.parse_optional_count()? {
+                Some((comparison, value)) => Ok(Query::Depth(comparison, value)),
+                None => Err(self.error("Expected a comparison such as \">\"")),
+            }
+        } else if self.try_consume_word("empty") {
+            Ok(Query::Empty)
+        } else {
+            Err(self.error("Expected a condition"))
+        }
+    }
+
+    /// Parses a comparison followed by a number, if there is a comparison.
+    fn parse_optional_count(&mut self) -> Result<Option<(Comparison, u128)>, String> {
+        let comparison = match self.peek() {
+            Some(QueryToken::Comparison(comparison)) => *comparison,
+            _ => return Ok(None),
+        };
+        self.position += 1;
+
+        match self.peek() {
+            Some(QueryToken::Number(value)) => {
+                let value = *value;
+                self.position += 1;
+                Ok(Some((comparison, value)))
+            }
+            _ => Err(self.error("Expected a number")),
+        }
+    }
+
+    fn parse_color(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(QueryToken::Color(color)) => {
+                let color = color.clone();
+                self.position += 1;
+                Ok(color)
+            }
+            _ => Err(self.error("Expected a quoted bag color")),
+        }
+    }
+
+    fn try_consume_word(&mut self, word: &str) -> bool {
+        match self.peek() {
+            Some(QueryToken::Word(w)) if w == word => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn peek(&self) -> Option<&QueryToken> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    /// Returns an error at the current token, or at the end of the query if there are no tokens
+    /// left.
+    fn error(&self, message: &str) -> String {
+        let column = self
+            .tokens
+            .get(self.position)
+            .map_or(self.end_column, |(column, _)| *column);
+
+        format!("Column {}: {}", column, message)
+    }
 }
 
-struct BagRules {
+/// A chain of bags where each bag directly contains the next, starting from the outermost bag.
+#[derive(Debug, PartialEq)]
+pub struct NestingChain {
+    pub outer_bag: String,
+    /// The number of each bag directly within the previous bag, along with its color.
+    pub steps: Vec<(u32, String)>,
+}
+
+impl fmt::Display for NestingChain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.outer_bag)?;
+        for (num, bag) in self.steps.iter() {
+            write!(f, " -({})-> {}", num, bag)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Which bags related to a chosen bag to highlight in a DOT graph.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Relatives {
+    /// The bags that can eventually contain the chosen bag.
+    Ancestors,
+    /// The bags that the chosen bag eventually contains.
+    Descendants,
+}
+
+#[derive(Debug, Default)]
+pub struct DotOptions {
+    /// A bag to highlight along with either its ancestors or its descendants.
+    pub highlight: Option<(String, Relatives)>,
+    /// If set, only the bags within this bag are included, along with the bag itself.
+    pub root: Option<String>,
+}
+
+/// The number of bags of each color within a bag, counting every level of nesting.
+pub type BagBreakdown = BTreeMap<String, u128>;
+
+pub struct BagRules {
     rules: BTreeMap<String, Vec<(String, u32)>>,
+    /// The bags that directly contain each bag.
+    containers: BTreeMap<String, BTreeSet<String>>,
+    /// Previously computed ancestors of bags, kept until a rule they depend on changes.
+    ancestors_cache: RefCell<BTreeMap<String, BTreeSet<String>>>,
+    /// Previously computed breakdowns of bags, kept until a rule they depend on changes.
+    breakdowns_cache: RefCell<BTreeMap<String, BagBreakdown>>,
 }
 
 impl BagRules {
-    fn from_lines(lines: &[String]) -> BagRules {
-        let rule_regex = self::regex::Regex::new(
-            r"^([a-z,\s]+) bags contain (((\d* ([a-z,\s])+ bags?,?\s?)|(no other bags))+).$",
-        )
-        .unwrap();
+    fn new(rules: BTreeMap<String, Vec<(String, u32)>>) -> BagRules {
+        let mut containers: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for (bag, contained_bags) in rules.iter() {
+            for (contained, _) in contained_bags.iter() {
+                containers
+                    .entry(contained.clone())
+                    .or_default()
+                    .insert(bag.clone());
+            }
+        }
+
+        BagRules {
+            rules,
+            containers,
+            ancestors_cache: RefCell::new(BTreeMap::new()),
+            breakdowns_cache: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// Adds a rule for the given bag, replacing any existing rule for it. Only the cached results
+    /// that depend on the rule are discarded.
+    pub fn set_rule(&mut self, bag: &str, contained_bags: Vec<(String, u32)>) {
+        self.invalidate_before_edit(bag);
+        self.remove_containers(bag);
+
+        for (contained, _) in contained_bags.iter() {
+            self.containers
+                .entry(contained.clone())
+                .or_default()
+                .insert(bag.to_string());
+        }
+        self.rules.insert(bag.to_string(), contained_bags);
 
-        let num_bags_regex = self::regex::Regex::new(r"^(\d+) ([a-z, ]+) bags?$").unwrap();
+        self.invalidate_after_edit(bag);
+    }
+
+    /// Removes the rule for the given bag, returning false if it had no rule. Only the cached
+    /// results that depend on the rule are discarded.
+    pub fn remove_rule(&mut self, bag: &str) -> bool {
+        if !self.rules.contains_key(bag) {
+            return false;
+        }
 
-        //    ^([a-z,\s]+) contain \d* ([a-z,\s])+ bags?.
-        //    ^([a-z,\s]+) contain (\d* ([a-z,\s])+ bags?,?\s?)+.
-        //    ^([a-z,\s]+) contain no other bags.
-        //    ^([a-z,\s]+) contain ((\d* ([a-z,\s])+ bags?,?\s?)|(no other bags))+.
+        self.invalidate_before_edit(bag);
+        self.remove_containers(bag);
+        self.rules.remove(bag);
 
+        true
+    }
+
+    fn remove_containers(&mut self, bag: &str) {
+        for (contained, _) in self.rules.get(bag).into_iter().flatten() {
+            if let Some(containers) = self.containers.get_mut(contained) {
+                containers.remove(bag);
+                if containers.is_empty() {
+                    self.containers.remove(contained);
+                }
+            }
+        }
+    }
+
+    /// Discards the cached results that depend on the rule for the given bag as it was before an
+    /// edit: the breakdowns of the bag and the bags that contain it, and the ancestors of the bags
+    /// within it.
+    fn invalidate_before_edit(&self, bag: &str) {
+        let mut breakdowns = self.breakdowns_cache.borrow_mut();
+        breakdowns.remove(bag);
+        for ancestor in self.find_ancestors(bag) {
+            breakdowns.remove(&ancestor);
+        }
+
+        self.invalidate_after_edit(bag);
+    }
+
+    /// Discards the cached ancestors of the bags within the given bag as it is after an edit.
+    fn invalidate_after_edit(&self, bag: &str) {
+        let mut ancestors = self.ancestors_cache.borrow_mut();
+        for descendant in self.descendants(bag) {
+            ancestors.remove(&descendant);
+        }
+    }
+
+    fn from_lines(lines: &[String]) -> Result<BagRules, SyntaxError> {
         let mut rules: BTreeMap<String, Vec<(String, u32)>> = BTreeMap::new();
-        for line in lines.iter() {
-            match rule_regex.captures(line) {
-                None => panic!(),
-                Some(capture) => {
-                    let bag_type = capture.get(1).unwrap().as_str();
-                    let contained_str = capture.get(2).unwrap().as_str();
-
-                    let contained_bags = if contained_str == "no other bags" {
-                        vec![]
-                    } else {
-                        contained_str
-                            .split(", ")
-                            .map(|t| match num_bags_regex.captures(t) {
-                                None => panic!(),
-                                Some(num_bags_capture) => {
-                                    let num_bags: u32 =
-                                        num_bags_capture.get(1).unwrap().as_str().parse().unwrap();
-                                    let contained_bag_type =
-                                        num_bags_capture.get(2).unwrap().as_str();
-
-                                    (contained_bag_type.to_string(), num_bags)
-                                }
-                            })
-                            .collect()
+        for (i, line) in lines.iter().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (bag, contained_bags) = RuleParser::new(line)
+                .parse_rule()
+                .map_err(|e| e.at_line(i + 1))?;
+            if rules.contains_key(&bag) {
+                return Err(SyntaxError {
+                    line: i + 1,
+                    column: 1,
+                    message: format!("Duplicate rule for {} bags", bag),
+                });
+            }
+
+            rules.insert(bag, contained_bags);
+        }
+
+        Ok(BagRules::new(rules))
+    }
+
+    /// Returns the number of bags of each color within the given bag, at every level of nesting.
+    /// The breakdown of each bag within it is only computed once.
+    pub fn breakdown(&self, bag: &str) -> Result<BagBreakdown, String> {
+        let mut breakdowns = self.breakdowns_cache.borrow_mut();
+        fill_breakdowns(self, bag, &mut vec![], &mut breakdowns)?;
+
+        Ok(breakdowns[bag].clone())
+    }
+
+    /// Returns the color of every bag with a rule that matches the given query.
+    pub fn matching(&self, query: &Query) -> Result<BTreeSet<String>, String> {
+        match query {
+            Query::DirectlyContains(bag, count) => Ok(self
+                .containers
+                .get(bag)
+                .into_iter()
+                .flatten()
+                .filter(|container| match count {
+                    // A rule can list the same color more than once, so add up all of its entries
+                    Some((comparison, value)) => comparison.matches(
+                        self.rules[*container]
+                            .iter()
+                            .filter(|(b, _)| b == bag)
+                            .map(|(_, num)| u128::from(*num))
+                            .sum(),
+                        *value,
+                    ),
+                    None => true,
+                })
+                .cloned()
+                .collect()),
+            Query::Contains(bag, count) => {
+                let mut matching = BTreeSet::new();
+                for ancestor in self.ancestors(bag) {
+                    let matches = match count {
+                        Some((comparison, value)) => {
+                            let num = self.breakdown(&ancestor)?.get(bag).cloned().unwrap_or(0);
+                            comparison.matches(num, *value)
+                        }
+                        None => true,
                     };
+                    if matches {
+                        matching.insert(ancestor);
+                    }
+                }
 
-                    rules.insert(bag_type.to_string(), contained_bags);
+                Ok(matching)
+            }
+            Query::Within(bag) => Ok(self
+                .descendants(bag)
+                .into_iter()
+                .filter(|b| self.rules.contains_key(b))
+                .collect()),
+            Query::Depth(comparison, value) => {
+                let mut depths = BTreeMap::new();
+                for bag in self.rules.keys() {
+                    fill_depths(self, bag, &mut vec![], &mut depths)?;
                 }
+
+                Ok(depths
+                    .into_iter()
+                    .filter(|(_, depth)| comparison.matches(u128::from(*depth), *value))
+                    .map(|(bag, _)| bag.to_string())
+                    .collect())
+            }
+            Query::Empty => Ok(self
+                .rules
+                .iter()
+                .filter(|(_, contained_bags)| contained_bags.is_empty())
+                .map(|(bag, _)| bag.clone())
+                .collect()),
+            Query::And(a, b) => Ok(self
+                .matching(a)?
+                .intersection(&self.matching(b)?)
+                .cloned()
+                .collect()),
+            Query::Or(a, b) => Ok(self
+                .matching(a)?
+                .union(&self.matching(b)?)
+                .cloned()
+                .collect()),
+            Query::Not(a) => {
+                let excluded = self.matching(a)?;
+                Ok(self
+                    .rules
+                    .keys()
+                    .filter(|b| !excluded.contains(*b))
+                    .cloned()
+                    .collect())
             }
         }
+    }
 
-        BagRules { rules }
+    /// Returns every bag that can eventually contain the given bag. The bag itself is only
+    /// included if it is part of a cycle of bags that contain each other.
+    pub fn ancestors(&self, bag: &str) -> BTreeSet<String> {
+        self.ancestors_cache
+            .borrow_mut()
+            .entry(bag.to_string())
+            .or_insert_with(|| self.find_ancestors(bag))
+            .clone()
     }
 
-    /*fn print(&self) {
-        for (k, v) in self.rules.iter() {
-            println!(
-                "{} -> {}",
-                k,
-                v.iter()
-                    .fold("".to_string(), |a, b| format!("{}   {} {}", a, b.1, b.0))
-            );
+    fn find_ancestors(&self, bag: &str) -> BTreeSet<String> {
+        let mut ancestors: BTreeSet<String> = BTreeSet::new();
+        let mut bags_to_visit: Vec<&str> = vec![bag];
+        while let Some(current_bag) = bags_to_visit.pop() {
+            if let Some(containers) = self.containers.get(current_bag) {
+                for container in containers.iter() {
+                    if ancestors.insert(container.clone()) {
+                        bags_to_visit.push(container);
+                    }
+                }
+            }
+        }
+
+        ancestors
+    }
+
+    /// Returns every bag that the given bag eventually contains. The bag itself is only included if
+    /// it is part of a cycle of bags that contain each other.
+    pub fn descendants(&self, bag: &str) -> BTreeSet<String> {
+        let mut descendants: BTreeSet<String> = BTreeSet::new();
+        let mut bags_to_visit: Vec<&str> = vec![bag];
+        while let Some(current_bag) = bags_to_visit.pop() {
+            for (contained, _) in self.rules.get(current_bag).into_iter().flatten() {
+                if descendants.insert(contained.clone()) {
+                    bags_to_visit.push(contained);
+                }
+            }
         }
-    }*/
+
+        descendants
+    }
+
+    /// Returns the rules as a DOT graph with one node per bag and one edge per contained bag,
+    /// labelled with the number of bags contained. Returns an error if the root or highlighted bag
+    /// is not mentioned in any rule.
+    pub fn to_dot(&self, options: &DotOptions) -> Result<String, String> {
+        let mut bags: BTreeSet<String> = self.rules.keys().cloned().collect();
+        bags.extend(self.containers.keys().cloned());
+
+        let given_bags = options
+            .root
+            .iter()
+            .chain(options.highlight.iter().map(|(bag, _)| bag));
+        for bag in given_bags {
+            if !bags.contains(bag) {
+                return Err(format!("Unknown bag color: {}", bag));
+            }
+        }
+        if let Some(root) = options.root.as_ref() {
+            bags = self.descendants(root);
+            bags.insert(root.clone());
+        }
+
+        let (highlighted_bag, relatives) = match options.highlight.as_ref() {
+            Some((bag, Relatives::Ancestors)) => (Some(bag), self.ancestors(bag)),
+            Some((bag, Relatives::Descendants)) => (Some(bag), self.descendants(bag)),
+            None => (None, BTreeSet::new()),
+        };
+        let is_highlighted = |bag: &String| highlighted_bag == Some(bag) || relatives.contains(bag);
+
+        let mut dot = "digraph bags {\n".to_string();
+        for bag in bags.iter() {
+            let attributes = if highlighted_bag == Some(bag) {
+                " [style=filled, fillcolor=gold]"
+            } else if relatives.contains(bag) {
+                " [style=filled, fillcolor=lightblue]"
+            } else {
+                ""
+            };
+            dot.push_str(&format!("    {}{};\n", dot_id(bag), attributes));
+        }
+        for (bag, contained_bags) in self.rules.iter().filter(|(b, _)| bags.contains(*b)) {
+            for (contained, num) in contained_bags.iter() {
+                let color = if is_highlighted(bag) && is_highlighted(contained) {
+                    ", color=blue"
+                } else {
+                    ""
+                };
+                dot.push_str(&format!(
+                    "    {} -> {} [label=\"{}\"{}];\n",
+                    dot_id(bag),
+                    dot_id(contained),
+                    num,
+                    color
+                ));
+            }
+        }
+        dot.push_str("}\n");
+
+        Ok(dot)
+    }
+
+    /// Returns a chain with the fewest steps from the outer bag down to the target bag, if the
+    /// outer bag can contain the target bag. Bags without a rule are treated as empty.
+    pub fn shortest_chain(&self, outer_bag: &str, target_bag: &str) -> Option<NestingChain> {
+        let mut parents: BTreeMap<&str, (&str, u32)> = BTreeMap::new();
+        let mut bags_to_visit: VecDeque<&str> = VecDeque::new();
+        bags_to_visit.push_back(outer_bag);
+        while let Some(current_bag) = bags_to_visit.pop_front() {
+            for (bag, num) in self.rules.get(current_bag).into_iter().flatten() {
+                if parents.contains_key(bag.as_str()) {
+                    continue;
+                }
+                parents.insert(bag, (current_bag, *num));
+
+                if bag == target_bag {
+                    let mut steps = vec![];
+                    let mut step_bag = target_bag;
+                    loop {
+                        let (parent, num) = parents[step_bag];
+                        steps.push((num, step_bag.to_string()));
+                        if parent == outer_bag {
+                            break;
+                        }
+                        step_bag = parent;
+                    }
+                    steps.reverse();
+
+                    return Some(NestingChain {
+                        outer_bag: outer_bag.to_string(),
+                        steps,
+                    });
+                }
+                bags_to_visit.push_back(bag);
+            }
+        }
+
+        None
+    }
+
+    /// Returns every chain from the outer bag down to the target bag that does not pass through
+    /// the same bag twice, ordered from shortest to longest.
+    pub fn all_chains(&self, outer_bag: &str, target_bag: &str) -> Vec<NestingChain> {
+        let mut chains = vec![];
+        self.find_chains(outer_bag, outer_bag, target_bag, &mut vec![], &mut chains);
+
+        chains.sort_by_key(|steps| steps.len());
+        chains
+            .into_iter()
+            .map(|steps| NestingChain {
+                outer_bag: outer_bag.to_string(),
+                steps,
+            })
+            .collect()
+    }
+
+    fn find_chains(
+        &self,
+        outer_bag: &str,
+        current_bag: &str,
+        target_bag: &str,
+        steps: &mut Vec<(u32, String)>,
+        chains: &mut Vec<Vec<(u32, String)>>,
+    ) {
+        for (bag, num) in self.rules.get(current_bag).into_iter().flatten() {
+            if steps.iter().any(|(_, b)| b == bag) {
+                continue;
+            }
+
+            steps.push((*num, bag.clone()));
+            if bag == target_bag {
+                chains.push(steps.clone());
+            } else if bag != outer_bag {
+                self.find_chains(outer_bag, bag, target_bag, steps, chains);
+            }
+            steps.pop();
+        }
+    }
+
+    /// Returns the bags directly within the given bag, or an error if the bag has no rule.
+    fn contained(&self, bag: &str) -> Result<&Vec<(String, u32)>, String> {
+        self.rules
+            .get(bag)
+            .ok_or_else(|| format!("No rule for {} bags", bag))
+    }
+
+    /// Returns every undefined color and every cycle in the rules. Each cycle is reported once,
+    /// starting from the first bag in the cycle to be visited.
+    pub fn validate(&self) -> Vec<RuleError> {
+        let mut errors = vec![];
+        for (bag, contained_bags) in self.rules.iter() {
+            for (contained, _) in contained_bags.iter() {
+                if !self.rules.contains_key(contained) {
+                    errors.push(RuleError::UndefinedColor {
+                        bag: bag.clone(),
+                        contained: contained.clone(),
+                    });
+                }
+            }
+        }
+
+        let mut finished: BTreeSet<&str> = BTreeSet::new();
+        for bag in self.rules.keys() {
+            self.find_cycles(bag, &mut vec![], &mut finished, &mut errors);
+        }
+
+        errors
+    }
+
+    fn find_cycles<'a>(
+        &'a self,
+        bag: &'a str,
+        stack: &mut Vec<&'a str>,
+        finished: &mut BTreeSet<&'a str>,
+        errors: &mut Vec<RuleError>,
+    ) {
+        if finished.contains(bag) {
+            return;
+        }
+        if let Some(start) = stack.iter().position(|b| *b == bag) {
+            errors.push(RuleError::Cycle(cycle_path(&stack[start..], bag)));
+            return;
+        }
+
+        if let Some(contained_bags) = self.rules.get(bag) {
+            stack.push(bag);
+            for (contained, _) in contained_bags.iter() {
+                self.find_cycles(contained, stack, finished, errors);
+            }
+            stack.pop();
+        }
+
+        finished.insert(bag);
+    }
+}
+
+impl fmt::Display for BagRules {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (bag, contained_bags) in self.rules.iter() {
+            write!(f, "{} bags contain ", bag)?;
+            if contained_bags.is_empty() {
+                write!(f, "no other bags")?;
+            }
+            for (i, (contained, num)) in contained_bags.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                let noun = if *num == 1 { "bag" } else { "bags" };
+                write!(f, "{} {} {}", num, contained, noun)?;
+            }
+            writeln!(f, ".")?;
+        }
+
+        Ok(())
+    }
 }
 
-fn read_input<R>(reader: R) -> BagRules
+/// A problem with the syntax of a bag rule, at the given line and column (both starting from 1).
+#[derive(Debug, PartialEq)]
+pub struct SyntaxError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl SyntaxError {
+    fn at_line(self, line: usize) -> SyntaxError {
+        SyntaxError { line, ..self }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+/// Parses a single rule of the form "<color> bags contain <contents>." where the contents are
+/// either "no other bags" or a comma separated list of "<number> <color> bag(s)".
+struct RuleParser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl RuleParser {
+    fn new(line: &str) -> RuleParser {
+        RuleParser {
+            chars: line.chars().collect(),
+            position: 0,
+        }
+    }
+
+    fn parse_rule(mut self) -> Result<(String, Vec<(String, u32)>), SyntaxError> {
+        let bag = self.parse_bag()?;
+        self.expect(" contain ")?;
+
+        let mut contained_bags = vec![];
+        if !self.try_consume("no other bags") {
+            loop {
+                let num = self.parse_number()?;
+                self.expect(" ")?;
+                contained_bags.push((self.parse_bag()?, num));
+
+                if !self.try_consume(", ") {
+                    break;
+                }
+            }
+        }
+
+        self.expect(".")?;
+        if self.position < self.chars.len() {
+            return Err(self.error("Unexpected text after the end of the rule"));
+        }
+
+        Ok((bag, contained_bags))
+    }
+
+    /// Parses a color made up of lowercase words, followed by "bag" or "bags".
+    fn parse_bag(&mut self) -> Result<String, SyntaxError> {
+        let mut words: Vec<String> = vec![];
+        loop {
+            let word_start = self.position;
+            let word: String = self.chars[self.position..]
+                .iter()
+                .take_while(|c| c.is_ascii_lowercase())
+                .collect();
+            if word.is_empty() {
+                return Err(self.error("Expected a bag color"));
+            }
+            self.position += word.len();
+
+            if word == "bag" || word == "bags" {
+                if words.is_empty() {
+                    self.position = word_start;
+                    return Err(self.error("Expected a bag color"));
+                }
+                return Ok(words.join(" "));
+            }
+            words.push(word);
+
+            if !self.try_consume(" ") {
+                return Err(self.error("Expected \"bags\" after the bag color"));
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<u32, SyntaxError> {
+        let digits: String = self.chars[self.position..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        if digits.is_empty() {
+            return Err(self.error("Expected a number of bags or \"no other bags\""));
+        }
+
+        let num = digits
+            .parse()
+            .map_err(|_| self.error("Number of bags is too large"))?;
+        self.position += digits.len();
+
+        Ok(num)
+    }
+
+    fn try_consume(&mut self, literal: &str) -> bool {
+        let literal: Vec<char> = literal.chars().collect();
+        if self.chars[self.position..].starts_with(&literal) {
+            self.position += literal.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), SyntaxError> {
+        if self.try_consume(literal) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected \"{}\"", literal.trim())))
+        }
+    }
+
+    /// Returns an error at the current position. The line is filled in by the caller.
+    fn error(&self, message: &str) -> SyntaxError {
+        SyntaxError {
+            line: 0,
+            column: self.position + 1,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Quotes a bag color for use as a DOT node ID.
+fn dot_id(bag: &str) -> String {
+    format!("\"{}\"", bag.replace('"', "\\\""))
+}
+
+/// Returns the bags in a cycle, repeating the first bag at the end.
+fn cycle_path(stack: &[&str], bag: &str) -> Vec<String> {
+    stack
+        .iter()
+        .chain(Some(&bag))
+        .map(|b| b.to_string())
+        .collect()
+}
+
+fn read_input<R>(reader: R) -> Result<BagRules, String>
 where
     R: BufRead,
 {
     let lines: Vec<String> = reader.lines().map(|l| l.unwrap()).collect();
-    BagRules::from_lines(&lines)
+    BagRules::from_lines(&lines).map_err(|e| e.to_string())
+}
+
+/// Reads the bag rules, failing with every problem found in them if they are not valid.
+fn read_valid_input<R>(reader: R) -> Result<BagRules, String>
+where
+    R: BufRead,
+{
+    let rules = read_input(reader)?;
+
+    let errors = rules.validate();
+    if errors.is_empty() {
+        Ok(rules)
+    } else {
+        Err(errors
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<String>>()
+            .join("\n"))
+    }
 }
 
+/// Returns the number of other bags that can eventually contain the desired bag.
 fn num_bags_can_contain(rules: &BagRules, desired_bag: &str) -> u32 {
     rules
-        .rules
-        .keys()
-        .filter(|b| {
-            let path = path_to(rules, b, desired_bag);
-
-            match path {
-                None => false,
-                Some(p) => p.len() > 1,
-            }
-        })
+        .ancestors(desired_bag)
+        .iter()
+        .filter(|b| *b != desired_bag)
         .count() as u32
 }
 
-fn path_to(rules: &BagRules, starting_bag: &str, target_bag: &str) -> Option<Vec<String>> {
-    let mut visited_bags: BTreeSet<String> = BTreeSet::new();
-    let mut bags_to_visit: BTreeSet<String> = BTreeSet::new();
+fn num_bags_within(rules: &BagRules, starting_bag: &str) -> Result<u128, String> {
+    total_bags(starting_bag, &rules.breakdown(starting_bag)?)
+}
 
-    let mut path: Vec<String> = vec![];
-    bags_to_visit.insert(starting_bag.to_string());
-    while !bags_to_visit.is_empty() {
-        let current_bag = bags_to_visit.pop_first().unwrap();
+fn total_bags(bag: &str, breakdown: &BagBreakdown) -> Result<u128, String> {
+    breakdown
+        .values()
+        .try_fold(0u128, |total, count| total.checked_add(*count))
+        .ok_or_else(|| too_many_bags(bag))
+}
 
-        visited_bags.insert(current_bag.clone());
-        path.push(current_bag.clone());
+fn too_many_bags(bag: &str) -> String {
+    format!("Too many bags within {} bags to count", bag)
+}
 
-        if current_bag == target_bag {
-            return Some(path);
-        }
+/// Fills in the breakdown of the given bag and every bag within it, reusing any breakdowns already
+/// computed. Keeps the stack of bags being counted so that a bag that contains itself is reported
+/// instead of recursing forever.
+fn fill_breakdowns<'a>(
+    rules: &'a BagRules,
+    bag: &'a str,
+    stack: &mut Vec<&'a str>,
+    breakdowns: &mut BTreeMap<String, BagBreakdown>,
+) -> Result<(), String> {
+    if breakdowns.contains_key(bag) {
+        return Ok(());
+    }
+    if let Some(start) = stack.iter().position(|b| *b == bag) {
+        return Err(RuleError::Cycle(cycle_path(&stack[start..], bag)).to_string());
+    }
 
-        for r in rules.rules.get(&current_bag).unwrap() {
-            if !visited_bags.contains(&r.0) {
-                bags_to_visit.insert(r.0.clone());
-            }
-        }
+    stack.push(bag);
+    let mut breakdown = BagBreakdown::new();
+    for (contained, num) in rules.contained(bag)?.iter() {
+        fill_breakdowns(rules, contained, stack, breakdowns)?;
 
-        if bags_to_visit.is_empty() {
-            return None;
+        let num = u128::from(*num);
+        let inner_counts = breakdowns[contained]
+            .iter()
+            .map(|(b, c)| (b, c.checked_mul(num)));
+        for (inner, count) in Some((contained, Some(num))).into_iter().chain(inner_counts) {
+            let total = breakdown.entry(inner.clone()).or_insert(0);
+            *total = count
+                .and_then(|c| total.checked_add(c))
+                .ok_or_else(|| too_many_bags(bag))?;
         }
     }
+    stack.pop();
 
-    panic!()
+    breakdowns.insert(bag.to_string(), breakdown);
+    Ok(())
 }
 
-fn num_bags_within(rules: &BagRules, starting_bag: &str) -> u32 {
-    rules
-        .rules
-        .get(starting_bag)
-        .unwrap()
+/// Fills in the nesting depth of the given bag and every bag within it, reporting any bag that
+/// contains itself.
+fn fill_depths<'a>(
+    rules: &'a BagRules,
+    bag: &'a str,
+    stack: &mut Vec<&'a str>,
+    depths: &mut BTreeMap<&'a str, u32>,
+) -> Result<u32, String> {
+    if let Some(depth) = depths.get(bag) {
+        return Ok(*depth);
+    }
+    if let Some(start) = stack.iter().position(|b| *b == bag) {
+        return Err(RuleError::Cycle(cycle_path(&stack[start..], bag)).to_string());
+    }
+
+    stack.push(bag);
+    let mut depth = 0;
+    for (contained, _) in rules.contained(bag)?.iter() {
+        depth = depth.max(fill_depths(rules, contained, stack, depths)? + 1);
+    }
+    stack.pop();
+
+    depths.insert(bag, depth);
+    Ok(depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "light red bags contain 1 bright white bag, 2 muted yellow bags.
+dark orange bags contain 3 bright white bags, 4 muted yellow bags.
+bright white bags contain 1 shiny gold bag.
+muted yellow bags contain 2 shiny gold bags, 9 faded blue bags.
+shiny gold bags contain 1 dark olive bag, 2 vibrant plum bags.
+dark olive bags contain 3 faded blue bags, 4 dotted black bags.
+vibrant plum bags contain 5 faded blue bags, 6 dotted black bags.
+faded blue bags contain no other bags.
+dotted black bags contain no other bags.
+";
+
+    #[test]
+    fn example_counts() {
+        let rules = read_valid_input(EXAMPLE.as_bytes()).unwrap();
+
+        assert_eq!(4, num_bags_can_contain(&rules, "shiny gold"));
+        assert_eq!(
+            vec!["bright white", "dark orange", "light red", "muted yellow"],
+            rules
+                .ancestors("shiny gold")
+                .iter()
+                .map(|b| b.as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert!(rules.ancestors("light red").is_empty());
+        assert!(rules.ancestors("pale teal").is_empty());
+        assert_eq!(Ok(32), num_bags_within(&rules, "shiny gold"));
+        assert_eq!(Ok(0), num_bags_within(&rules, "faded blue"));
+    }
+
+    #[test]
+    fn example_chains() {
+        let rules = read_valid_input(EXAMPLE.as_bytes()).unwrap();
+
+        assert_eq!(
+            "light red -(1)-> bright white -(1)-> shiny gold",
+            rules
+                .shortest_chain("light red", "shiny gold")
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            "shiny gold -(1)-> dark olive -(3)-> faded blue",
+            rules
+                .shortest_chain("shiny gold", "faded blue")
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(None, rules.shortest_chain("shiny gold", "light red"));
+        assert_eq!(None, rules.shortest_chain("shiny gold", "shiny gold"));
+
+        let chains: Vec<String> = rules
+            .all_chains("light red", "shiny gold")
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "light red -(1)-> bright white -(1)-> shiny gold",
+                "light red -(2)-> muted yellow -(2)-> shiny gold",
+            ],
+            chains
+        );
+        assert_eq!(5, rules.all_chains("light red", "faded blue").len());
+        assert!(rules.all_chains("faded blue", "shiny gold").is_empty());
+    }
+
+    #[test]
+    fn example_dot() {
+        let input = "light red bags contain 1 bright white bag, 2 muted yellow bags.
+bright white bags contain 1 shiny gold bag.
+muted yellow bags contain 3 faded blue bags.
+shiny gold bags contain 4 dotted black bags.
+faded blue bags contain no other bags.
+dotted black bags contain no other bags.
+";
+        let rules = read_valid_input(input.as_bytes()).unwrap();
+
+        let options = DotOptions {
+            highlight: Some(("shiny gold".to_string(), Relatives::Ancestors)),
+            root: None,
+        };
+        assert_eq!(
+            Ok("digraph bags {
+    \"bright white\" [style=filled, fillcolor=lightblue];
+    \"dotted black\";
+    \"faded blue\";
+    \"light red\" [style=filled, fillcolor=lightblue];
+    \"muted yellow\";
+    \"shiny gold\" [style=filled, fillcolor=gold];
+    \"bright white\" -> \"shiny gold\" [label=\"1\", color=blue];
+    \"light red\" -> \"bright white\" [label=\"1\", color=blue];
+    \"light red\" -> \"muted yellow\" [label=\"2\"];
+    \"muted yellow\" -> \"faded blue\" [label=\"3\"];
+    \"shiny gold\" -> \"dotted black\" [label=\"4\"];
+}
+"
+            .to_string()),
+            rules.to_dot(&options)
+        );
+
+        let options = DotOptions {
+            highlight: Some(("bright white".to_string(), Relatives::Descendants)),
+            root: Some("bright white".to_string()),
+        };
+        assert_eq!(
+            Ok("digraph bags {
+    \"bright white\" [style=filled, fillcolor=gold];
+    \"dotted black\" [style=filled, fillcolor=lightblue];
+    \"shiny gold\" [style=filled, fillcolor=lightblue];
+    \"bright white\" -> \"shiny gold\" [label=\"1\", color=blue];
+    \"shiny gold\" -> \"dotted black\" [label=\"4\", color=blue];
+}
+"
+            .to_string()),
+            rules.to_dot(&options)
+        );
+
+        let options = DotOptions {
+            root: Some("pale teal".to_string()),
+            ..DotOptions::default()
+        };
+        assert_eq!(
+            Err("Unknown bag color: pale teal".to_string()),
+            rules.to_dot(&options)
+        );
+        let options = DotOptions {
+            highlight: Some(("pale teal".to_string(), Relatives::Ancestors)),
+            ..DotOptions::default()
+        };
+        assert!(rules.to_dot(&options).is_err());
+    }
+
+    #[test]
+    fn example_breakdown() {
+        let rules = read_valid_input(EXAMPLE.as_bytes()).unwrap();
+        let breakdown = rules.breakdown("shiny gold").unwrap();
+
+        let expected: BagBreakdown = [
+            ("dark olive", 1),
+            ("dotted black", 16),
+            ("faded blue", 13),
+            ("vibrant plum", 2),
+        ]
         .iter()
-        .map(|(b, num)| (num_bags_within(rules, b) + 1) * num)
-        .sum()
+        .map(|(b, c)| (b.to_string(), *c))
+        .collect();
+        assert_eq!(expected, breakdown);
+
+        assert_eq!(
+            Ok("shiny gold bags contain 32 bags
+  16 dotted black (50.0%)
+  13 faded blue (40.6%)
+"
+            .to_string()),
+            format_breakdown("shiny gold", &breakdown, 2)
+        );
+        assert!(rules.breakdown("pale teal").is_err());
+    }
+
+    #[test]
+    fn breakdown_zero_bags() {
+        let rules = read_valid_input(
+            "a bags contain 0 b bags.\nb bags contain no other bags.\n".as_bytes(),
+        )
+        .unwrap();
+        let breakdown = rules.breakdown("a").unwrap();
+
+        assert_eq!(
+            Ok("a bags contain 0 bags\n".to_string()),
+            format_breakdown("a", &breakdown, 5)
+        );
+    }
+
+    #[test]
+    fn breakdown_overflow() {
+        let mut lines: Vec<String> = (0..5)
+            .map(|i| {
+                format!(
+                    "level {} bags contain 4294967295 level {} bags.",
+                    to_word(i),
+                    to_word(i + 1)
+                )
+            })
+            .collect();
+        lines.push(format!("level {} bags contain no other bags.", to_word(5)));
+        let rules = BagRules::from_lines(&lines).unwrap();
+
+        assert_eq!(
+            Ok(u128::from(u32::MAX).pow(3)),
+            rules
+                .breakdown("level c")
+                .map(|b| b[&format!("level {}", to_word(5))])
+        );
+        assert_eq!(
+            Err("Too many bags within level a bags to count".to_string()),
+            num_bags_within(&rules, "level a")
+        );
+    }
+
+    /// Returns a lowercase word for the given index, since bag colors cannot contain digits.
+    fn to_word(i: u8) -> String {
+        ((b'a' + i) as char).to_string()
+    }
+
+    #[test]
+    fn edit_rules() {
+        let mut rules = read_valid_input(EXAMPLE.as_bytes()).unwrap();
+
+        assert_eq!(Ok(186), num_bags_within(&rules, "light red"));
+        assert_eq!(4, num_bags_can_contain(&rules, "shiny gold"));
+        assert_eq!(7, num_bags_can_contain(&rules, "faded blue"));
+
+        rules.set_rule(
+            "vibrant plum",
+            vec![("faded blue".to_string(), 1), ("pale teal".to_string(), 2)],
+        );
+        assert_eq!(
+            vec!["dark olive", "dotted black", "faded blue"],
+            rules
+                .breakdowns_cache
+                .borrow()
+                .keys()
+                .map(|b| b.as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert_eq!(
+            vec!["shiny gold"],
+            rules
+                .ancestors_cache
+                .borrow()
+                .keys()
+                .map(|b| b.as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert_eq!(6, rules.ancestors("pale teal").len());
+        assert!(num_bags_within(&rules, "shiny gold").is_err());
+
+        rules.set_rule("pale teal", vec![]);
+        assert_eq!(Ok(16), num_bags_within(&rules, "shiny gold"));
+        assert_eq!(6, rules.ancestors("pale teal").len());
+
+        assert!(rules.remove_rule("dark orange"));
+        assert!(!rules.remove_rule("dark orange"));
+        assert_eq!(3, num_bags_can_contain(&rules, "shiny gold"));
+        assert_eq!(5, num_bags_can_contain(&rules, "pale teal"));
+        assert_eq!(Ok(106), num_bags_within(&rules, "light red"));
+
+        rules.set_rule("faded blue", vec![("light red".to_string(), 1)]);
+        assert!(num_bags_within(&rules, "light red").is_err());
+        assert_eq!(7, num_bags_can_contain(&rules, "pale teal"));
+    }
+
+    #[test]
+    fn interactive_commands() {
+        let mut rules = read_valid_input(EXAMPLE.as_bytes()).unwrap();
+
+        assert_eq!(
+            "shiny gold bags contain 32 bags",
+            run_command(&mut rules, "within shiny gold")
+        );
+        assert_eq!(
+            "Set rule for dark olive bags",
+            run_command(&mut rules, "set dark olive bags contain no other bags.")
+        );
+        assert_eq!(
+            "shiny gold bags contain 25 bags",
+            run_command(&mut rules, "within shiny gold")
+        );
+        assert_eq!(
+            "5 bags can contain dark olive bags",
+            run_command(&mut rules, "contains dark olive")
+        );
+        assert_eq!(
+            "Removed rule for vibrant plum bags",
+            run_command(&mut rules, "del vibrant plum")
+        );
+        assert_eq!(
+            "No rule for vibrant plum bags",
+            run_command(&mut rules, "within shiny gold")
+        );
+        assert_eq!(
+            "Column 20: Expected \"contain\"",
+            run_command(&mut rules, "set dark olive bags hold no other bags.")
+        );
+        assert_eq!(
+            "Column 22: Expected \"contain\"",
+            run_command(&mut rules, "set   dark olive bags hold no other bags.")
+        );
+        assert_eq!("Unknown command: add", run_command(&mut rules, "add x"));
+    }
+
+    fn query_example(query: &str) -> Result<Vec<String>, String> {
+        let rules = read_valid_input(EXAMPLE.as_bytes()).unwrap();
+
+        rules
+            .matching(&query.parse()?)
+            .map(|bags| bags.into_iter().collect())
+    }
+
+    #[test]
+    fn example_queries() {
+        assert_eq!(
+            Ok(vec!["muted yellow".to_string()]),
+            query_example("directly contains >= 2 \"shiny gold\"")
+        );
+        assert_eq!(
+            Ok(vec!["bright white".to_string(), "muted yellow".to_string()]),
+            query_example("directly contains \"shiny gold\"")
+        );
+        assert_eq!(
+            Ok(vec!["dark orange".to_string(), "light red".to_string()]),
+            query_example("depth > 3")
+        );
+        assert_eq!(
+            Ok(vec!["dark olive".to_string(), "vibrant plum".to_string()]),
+            query_example("not (empty or depth >= 2)")
+        );
+        assert_eq!(
+            Ok(vec!["dotted black".to_string(), "faded blue".to_string()]),
+            query_example("empty and within \"shiny gold\"")
+        );
+        assert_eq!(
+            Ok(vec![
+                "bright white".to_string(),
+                "dark orange".to_string(),
+                "light red".to_string(),
+                "muted yellow".to_string(),
+                "shiny gold".to_string(),
+            ]),
+            query_example("contains >= 10 \"faded blue\"")
+        );
+        assert_eq!(
+            Ok(vec!["dark olive".to_string(), "vibrant plum".to_string()]),
+            query_example(
+                "contains \"faded blue\" and contains \"dotted black\" and not depth > 1"
+            )
+        );
+        assert_eq!(
+            Ok(vec!["dotted black".to_string(), "faded blue".to_string()]),
+            query_example("empty or directly contains = 2 \"shiny gold\" and depth > 3")
+        );
+    }
+
+    #[test]
+    fn query_directly_contains_repeated_color() {
+        let input = "light red bags contain 1 shiny gold bag, 2 shiny gold bags.
+shiny gold bags contain no other bags.
+";
+        let rules = read_input(input.as_bytes()).unwrap();
+        let matching = |query: &str| {
+            rules
+                .matching(&query.parse().unwrap())
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(
+            vec!["light red".to_string()],
+            matching("directly contains = 3 \"shiny gold\"")
+        );
+        assert!(matching("directly contains = 2 \"shiny gold\"").is_empty());
+    }
+
+    #[test]
+    fn query_syntax_errors() {
+        assert_eq!(
+            Err("Column 9: Expected a number".to_string()),
+            query_example("depth > x")
+        );
+        assert_eq!(
+            Err("Column 10: Expected a quoted bag color".to_string()),
+            query_example("contains shiny gold")
+        );
+        assert_eq!(
+            Err("Column 10: Expected a condition".to_string()),
+            query_example("empty and")
+        );
+        assert_eq!(
+            Err("Column 7: Expected \")\"".to_string()),
+            query_example("(empty")
+        );
+        assert_eq!(
+            Err("Column 7: Unexpected text after the end of the query".to_string()),
+            query_example("empty empty")
+        );
+        assert_eq!(
+            Err("Column 8: Unterminated bag color".to_string()),
+            query_example("within \"shiny gold")
+        );
+        assert_eq!(
+            Err("Column 7: Unexpected character '!'".to_string()),
+            query_example("depth != 2")
+        );
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let rules = read_input(EXAMPLE.as_bytes()).unwrap();
+        let formatted = rules.to_string();
+
+        assert_eq!(
+            "bright white bags contain 1 shiny gold bag.
+dark olive bags contain 3 faded blue bags, 4 dotted black bags.
+dark orange bags contain 3 bright white bags, 4 muted yellow bags.
+dotted black bags contain no other bags.
+faded blue bags contain no other bags.
+light red bags contain 1 bright white bag, 2 muted yellow bags.
+muted yellow bags contain 2 shiny gold bags, 9 faded blue bags.
+shiny gold bags contain 1 dark olive bag, 2 vibrant plum bags.
+vibrant plum bags contain 5 faded blue bags, 6 dotted black bags.
+",
+            formatted
+        );
+        assert_eq!(
+            formatted,
+            read_input(formatted.as_bytes()).unwrap().to_string()
+        );
+
+        let rules = read_input("pale teal bags contain 1 dim red bags.\n\n".as_bytes()).unwrap();
+        assert_eq!("pale teal bags contain 1 dim red bag.\n", rules.to_string());
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let parse_error = |line: &str| {
+            BagRules::from_lines(&[
+                "faded blue bags contain no other bags.".to_string(),
+                line.to_string(),
+            ])
+            .err()
+            .map(|e| e.to_string())
+        };
+
+        assert_eq!(
+            Some("Line 2, column 15: Expected \"contain\"".to_string()),
+            parse_error("light red bags contains 1 bright white bag.")
+        );
+        assert_eq!(
+            Some("Line 2, column 24: Expected a number of bags or \"no other bags\"".to_string()),
+            parse_error("light red bags contain one bright white bag.")
+        );
+        assert_eq!(
+            Some("Line 2, column 38: Expected \"bags\" after the bag color".to_string()),
+            parse_error("light red bags contain 1 bright white.")
+        );
+        assert_eq!(
+            Some("Line 2, column 26: Expected a bag color".to_string()),
+            parse_error("light red bags contain 1 bags.")
+        );
+        assert_eq!(
+            Some("Line 2, column 42: Expected \".\"".to_string()),
+            parse_error("light red bags contain 1 bright white bag; 2 muted yellow bags.")
+        );
+        assert_eq!(
+            Some("Line 2, column 43: Unexpected text after the end of the rule".to_string()),
+            parse_error("light red bags contain 1 bright white bag..")
+        );
+        assert_eq!(
+            Some("Line 2, column 1: Duplicate rule for faded blue bags".to_string()),
+            parse_error("faded blue bags contain 1 bright white bag.")
+        );
+        assert_eq!(
+            Some("Line 2, column 1: Expected a bag color".to_string()),
+            parse_error("Light red bags contain no other bags.")
+        );
+    }
+
+    #[test]
+    fn validate_undefined_colors() {
+        let input = "light red bags contain 1 bright white bag, 2 muted yellow bags.
+bright white bags contain no other bags.
+";
+        let rules = read_input(input.as_bytes()).unwrap();
+
+        assert_eq!(
+            vec![RuleError::UndefinedColor {
+                bag: "light red".to_string(),
+                contained: "muted yellow".to_string(),
+            }],
+            rules.validate()
+        );
+        assert_eq!(
+            Err("No rule for muted yellow bags".to_string()),
+            num_bags_within(&rules, "light red")
+        );
+        assert_eq!(1, num_bags_can_contain(&rules, "muted yellow"));
+        assert_eq!(
+            Err("light red bags contain muted yellow bags, which have no rule".to_string()),
+            read_valid_input(input.as_bytes()).map(|r| r.rules.len())
+        );
+    }
+
+    #[test]
+    fn validate_cycles() {
+        let input = "light red bags contain 1 bright white bag.
+bright white bags contain 2 muted yellow bags, 1 faded blue bag.
+muted yellow bags contain 1 light red bag.
+faded blue bags contain 1 faded blue bag.
+";
+        let rules = read_input(input.as_bytes()).unwrap();
+
+        let errors = rules.validate();
+        assert_eq!(2, errors.len());
+        assert_eq!(
+            "Bags contain themselves: bright white -> muted yellow -> light red -> bright white",
+            errors[0].to_string()
+        );
+        assert_eq!(
+            "Bags contain themselves: faded blue -> faded blue",
+            errors[1].to_string()
+        );
+
+        assert_eq!(
+            Err(
+                "Bags contain themselves: light red -> bright white -> muted yellow -> light red"
+                    .to_string()
+            ),
+            num_bags_within(&rules, "light red")
+        );
+        assert_eq!(2, num_bags_can_contain(&rules, "light red"));
+        assert_eq!(3, rules.ancestors("light red").len());
+
+        assert_eq!(
+            "light red -(1)-> bright white -(2)-> muted yellow -(1)-> light red",
+            rules
+                .shortest_chain("light red", "light red")
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(1, rules.all_chains("light red", "light red").len());
+    }
+
+    #[test]
+    fn all_chains_cycle_through_outer_bag() {
+        let input = "light red bags contain 1 bright white bag.
+bright white bags contain 2 muted yellow bags, 1 faded blue bag.
+muted yellow bags contain 1 light red bag.
+faded blue bags contain no other bags.
+";
+        let rules = read_input(input.as_bytes()).unwrap();
+
+        let chains: Vec<String> = rules
+            .all_chains("bright white", "faded blue")
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(vec!["bright white -(1)-> faded blue"], chains);
+        assert_eq!(1, rules.all_chains("light red", "faded blue").len());
+    }
 }
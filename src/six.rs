@@ -1,80 +1,437 @@
-use std::collections::BTreeSet;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap};
 use std::io::{self, BufRead};
+use std::str::FromStr;
 
 pub fn part_one() {
-    let groups = read_input(io::stdin().lock());
-    let answer = sum_num_questions_any_anwered_yes(&groups);
-
-    println!("{}", answer)
+    part_one_with_alphabet(&Alphabet::default())
 }
 
 pub fn part_two() {
-    let groups = read_input(io::stdin().lock());
-    let answer = sum_num_questions_all_anwered_yes(&groups);
+    part_two_with_alphabet(&Alphabet::default())
+}
+
+pub fn part_one_with_alphabet(alphabet: &Alphabet) {
+    match read_input(io::stdin().lock(), alphabet) {
+        Ok(groups) => println!("{}", sum_num_questions_any_anwered_yes(&groups)),
+        Err(msg) => println!("{}", msg),
+    }
+}
+
+pub fn part_two_with_alphabet(alphabet: &Alphabet) {
+    match read_input(io::stdin().lock(), alphabet) {
+        Ok(groups) => println!("{}", sum_num_questions_all_anwered_yes(&groups)),
+        Err(msg) => println!("{}", msg),
+    }
+}
+
+/// Prints the sum over all groups of the number of questions answered yes by at least the given
+/// number of members of the group, or by exactly that number of members if `exact` is set. The
+/// number of members must be at least one, since only questions someone answered are tracked.
+pub fn quorum(alphabet: &Alphabet, min_members: usize, exact: bool) {
+    if min_members == 0 {
+        println!("The number of members must be at least 1");
+        return;
+    }
+
+    let groups = match read_input(io::stdin().lock(), alphabet) {
+        Ok(groups) => groups,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+
+    let answer: usize = groups
+        .iter()
+        .map(|g| {
+            if exact {
+                g.num_questions_answered_by_exactly(min_members)
+            } else {
+                g.num_questions_answered_by_at_least(min_members)
+            }
+        })
+        .sum();
 
     println!("{}", answer)
 }
 
-struct GroupAnswers {
-    member_answers: Vec<BTreeSet<char>>,
+/// Prints, for each group and then for all groups combined, the number of members that answered
+/// yes to each question and the number of questions answered yes by each number of members.
+pub fn histogram(alphabet: &Alphabet) {
+    let groups = match read_input(io::stdin().lock(), alphabet) {
+        Ok(groups) => groups,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+
+    let mut overall_counts: BTreeMap<char, usize> = BTreeMap::new();
+    let mut overall_histogram: BTreeMap<usize, usize> = BTreeMap::new();
+    for (i, group) in groups.iter().enumerate() {
+        let counts = group.answer_counts(alphabet);
+        let histogram = group.member_histogram();
+
+        println!(
+            "Group {} ({} members): {}",
+            i + 1,
+            group.member_answers.len(),
+            format_counts(&counts)
+        );
+        println!("  by members: {}", format_counts(&histogram));
+
+        for (question, count) in counts {
+            *overall_counts.entry(question).or_insert(0) += count;
+        }
+        for (num_members, count) in histogram {
+            *overall_histogram.entry(num_members).or_insert(0) += count;
+        }
+    }
+
+    println!(
+        "Overall ({} groups, {} members): {}",
+        groups.len(),
+        groups.iter().map(|g| g.member_answers.len()).sum::<usize>(),
+        format_counts(&overall_counts)
+    );
+    println!("  by members: {}", format_counts(&overall_histogram));
 }
 
-impl GroupAnswers {
-    fn from_lines(lines: &[String]) -> GroupAnswers {
-        let mut member_answers: Vec<BTreeSet<char>> = vec![];
-        for line in lines.iter() {
-            let mut answers: BTreeSet<char> = BTreeSet::new();
-            for c in line.chars() {
-                answers.insert(c);
+/// Prints a CSV comparing the questions answered by each group, using the Jaccard similarity of
+/// the questions anyone in each group answered yes to. Lists the given number of most similar and
+/// most dissimilar pairs of groups, without listing any pair twice, followed by the clusters of
+/// groups linked by pairs at or above the similarity threshold.
+pub fn similarity(alphabet: &Alphabet, threshold: f64, top: usize) {
+    match read_input(io::stdin().lock(), alphabet) {
+        Ok(groups) => print!("{}", similarity_csv(&groups, threshold, top)),
+        Err(msg) => println!("{}", msg),
+    }
+}
+
+/// Returns the Jaccard similarity of two sets of answers. Two empty sets are identical.
+fn jaccard_similarity(a: Answers, b: Answers) -> f64 {
+    let union = (a | b).count_ones();
+    if union == 0 {
+        1.0
+    } else {
+        (a & b).count_ones() as f64 / union as f64
+    }
+}
+
+/// A pair of groups, by index, and their similarity.
+type GroupPair = (usize, usize, f64);
+
+/// A pair of groups ordered by similarity, where ties are broken so that earlier pairs of groups
+/// rank as more similar.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct RankedPair(GroupPair);
+
+impl Eq for RankedPair {}
+
+impl PartialOrd for RankedPair {
+    fn partial_cmp(&self, other: &RankedPair) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RankedPair {
+    fn cmp(&self, other: &RankedPair) -> Ordering {
+        let (a_i, a_j, a_sim) = self.0;
+        let (b_i, b_j, b_sim) = other.0;
+
+        // Similarities are never NaN, since they are ratios of counts with a non-zero denominator
+        a_sim
+            .partial_cmp(&b_sim)
+            .unwrap()
+            .then((b_i, b_j).cmp(&(a_i, a_j)))
+    }
+}
+
+/// Returns the given number of most similar pairs of groups, from most to least similar, and the
+/// given number of most dissimilar pairs, from least to most similar.
+///
+/// Only the top pairs are kept in bounded heaps while the pairs are compared, so the pairs do not
+/// all need to fit in memory. When there are fewer than twice the given number of pairs, the most
+/// dissimilar pairs are cut short so that no pair is in both lists.
+fn top_pairs(unions: &[Answers], top: usize) -> (Vec<GroupPair>, Vec<GroupPair>) {
+    let num_pairs = unions.len() * unions.len().saturating_sub(1) / 2;
+    let num_similar = top.min(num_pairs);
+    let num_dissimilar = top.min(num_pairs - num_similar);
+
+    // The most similar pairs are kept in a min-heap, so the least similar of them is removed when
+    // the heap is over capacity, and the reverse for the most dissimilar pairs
+    let mut most_similar: BinaryHeap<Reverse<RankedPair>> = BinaryHeap::with_capacity(top + 1);
+    let mut most_dissimilar: BinaryHeap<RankedPair> = BinaryHeap::with_capacity(top + 1);
+    for (i, a) in unions.iter().enumerate() {
+        for (j, b) in unions.iter().enumerate().skip(i + 1) {
+            let pair = RankedPair((i, j, jaccard_similarity(*a, *b)));
+
+            most_similar.push(Reverse(pair));
+            if most_similar.len() > num_similar {
+                most_similar.pop();
             }
 
-            member_answers.push(answers);
+            most_dissimilar.push(pair);
+            if most_dissimilar.len() > num_dissimilar {
+                most_dissimilar.pop();
+            }
         }
+    }
+
+    (
+        most_similar
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(RankedPair(pair))| pair)
+            .collect(),
+        most_dissimilar
+            .into_sorted_vec()
+            .into_iter()
+            .map(|RankedPair(pair)| pair)
+            .collect(),
+    )
+}
+
+/// Returns the clusters of groups connected by pairs at or above the similarity threshold, leaving
+/// out groups that are not similar to any other group. Each cluster lists its groups in order, and
+/// clusters are ordered by their first group.
+fn cluster_groups(unions: &[Answers], threshold: f64) -> Vec<Vec<usize>> {
+    let mut parents: Vec<usize> = (0..unions.len()).collect();
+    fn find(parents: &mut [usize], group: usize) -> usize {
+        let mut root = group;
+        while parents[root] != root {
+            root = parents[root];
+        }
+        parents[group] = root;
+        root
+    }
+
+    for (i, a) in unions.iter().enumerate() {
+        for (j, b) in unions.iter().enumerate().skip(i + 1) {
+            if jaccard_similarity(*a, *b) >= threshold {
+                let (root_i, root_j) = (find(&mut parents, i), find(&mut parents, j));
+                parents[root_i.max(root_j)] = root_i.min(root_j);
+            }
+        }
+    }
+
+    let mut clusters: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for group in 0..unions.len() {
+        let root = find(&mut parents, group);
+        clusters.entry(root).or_default().push(group);
+    }
+
+    clusters
+        .into_values()
+        .filter(|groups| groups.len() > 1)
+        .collect()
+}
+
+fn similarity_csv(groups: &[GroupAnswers], threshold: f64, top: usize) -> String {
+    let unions: Vec<Answers> = groups.iter().map(GroupAnswers::union).collect();
+    let (most_similar, most_dissimilar) = top_pairs(&unions, top);
+
+    let mut csv = "kind,group_a,group_b,similarity,cluster\n".to_string();
+    for (i, j, similarity) in most_similar.iter() {
+        csv.push_str(&format!(
+            "most_similar,{},{},{:.4},\n",
+            i + 1,
+            j + 1,
+            similarity
+        ));
+    }
+    for (i, j, similarity) in most_dissimilar.iter() {
+        csv.push_str(&format!(
+            "most_dissimilar,{},{},{:.4},\n",
+            i + 1,
+            j + 1,
+            similarity
+        ));
+    }
+    for (c, cluster) in cluster_groups(&unions, threshold).iter().enumerate() {
+        for group in cluster.iter() {
+            csv.push_str(&format!("cluster,{},,,{}\n", group + 1, c + 1));
+        }
+    }
+
+    csv
+}
+
+fn format_counts<K: ToString>(counts: &BTreeMap<K, usize>) -> String {
+    counts
+        .iter()
+        .map(|(k, count)| format!("{}={}", k.to_string(), count))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// The questions that can be answered on a customs form, one per character. Each member's answers
+/// are stored as a bitset with one bit per question, in alphabet order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alphabet {
+    questions: Vec<char>,
+}
+
+impl Alphabet {
+    pub fn new(questions: &str) -> Result<Alphabet, String> {
+        let questions: Vec<char> = questions.chars().collect();
+        if questions.is_empty() || questions.len() > Answers::BITS as usize {
+            return Err(format!(
+                "Alphabet must have between 1 and {} questions, found {}",
+                Answers::BITS,
+                questions.len()
+            ));
+        }
+        for (i, question) in questions.iter().enumerate() {
+            if questions[..i].contains(question) {
+                return Err(format!("Question '{}' appears twice in alphabet", question));
+            }
+        }
+
+        Ok(Alphabet { questions })
+    }
+
+    /// Returns the bitset of the questions answered on the given line.
+    fn parse_answers(&self, line: &str) -> Result<Answers, String> {
+        let mut answers = 0;
+        for c in line.chars() {
+            match self.questions.iter().position(|q| *q == c) {
+                Some(i) => answers |= 1 << i,
+                None => {
+                    return Err(format!(
+                        "Question '{}' is not in the alphabet \"{}\"",
+                        c,
+                        self.questions.iter().collect::<String>()
+                    ))
+                }
+            }
+        }
+
+        Ok(answers)
+    }
+}
+
+impl FromStr for Alphabet {
+    type Err = String;
+
+    fn from_str(questions: &str) -> Result<Alphabet, String> {
+        Alphabet::new(questions)
+    }
+}
 
-        GroupAnswers { member_answers }
+impl Default for Alphabet {
+    fn default() -> Alphabet {
+        Alphabet::new("abcdefghijklmnopqrstuvwxyz").unwrap()
+    }
+}
+
+type Answers = u64;
+
+struct GroupAnswers {
+    member_answers: Vec<Answers>,
+}
+
+impl GroupAnswers {
+    /// Returns the questions that anyone in the group answered yes to.
+    fn union(&self) -> Answers {
+        self.member_answers.iter().fold(0, |a, b| a | b)
     }
 
     fn num_questions_any_anwered_yes(&self) -> i32 {
-        self.member_answers
-            .iter()
-            .fold(BTreeSet::new(), |mut a, b| {
-                b.iter().for_each(|c| {
-                    a.insert(c);
-                });
-                a
-            })
-            .len() as i32
+        self.union().count_ones() as i32
     }
 
     fn num_questions_all_anwered_yes(&self) -> i32 {
         if self.member_answers.is_empty() {
             0
         } else {
-            self.member_answers[0]
+            self.member_answers
                 .iter()
-                .filter(|a| self.member_answers.iter().all(|ma| ma.contains(a)))
-                .count() as i32
+                .fold(!0, |a, b| a & b)
+                .count_ones() as i32
+        }
+    }
+
+    /// Returns the number of members that answered yes to each question, by question index.
+    /// Questions that no member answered are left out.
+    fn member_counts(&self) -> BTreeMap<u32, usize> {
+        let mut counts = BTreeMap::new();
+        for answers in self.member_answers.iter() {
+            let mut remaining = *answers;
+            while remaining != 0 {
+                *counts.entry(remaining.trailing_zeros()).or_insert(0) += 1;
+                remaining &= remaining - 1;
+            }
+        }
+
+        counts
+    }
+
+    /// Returns the number of members that answered yes to each question.
+    fn answer_counts(&self, alphabet: &Alphabet) -> BTreeMap<char, usize> {
+        self.member_counts()
+            .into_iter()
+            .map(|(i, count)| (alphabet.questions[i as usize], count))
+            .collect()
+    }
+
+    /// Returns the number of questions that were answered yes by each number of members.
+    fn member_histogram(&self) -> BTreeMap<usize, usize> {
+        let mut histogram = BTreeMap::new();
+        for count in self.member_counts().values() {
+            *histogram.entry(*count).or_insert(0) += 1;
         }
+
+        histogram
+    }
+
+    /// Returns the number of questions answered yes by at least the given number of members. A
+    /// question must be answered by at least one member to count.
+    fn num_questions_answered_by_at_least(&self, min_members: usize) -> usize {
+        self.member_counts()
+            .values()
+            .filter(|count| **count >= min_members)
+            .count()
+    }
+
+    fn num_questions_answered_by_exactly(&self, num_members: usize) -> usize {
+        self.member_counts()
+            .values()
+            .filter(|count| **count == num_members)
+            .count()
     }
 }
 
-fn read_input<R>(reader: R) -> Vec<GroupAnswers>
+fn read_input<R>(reader: R, alphabet: &Alphabet) -> Result<Vec<GroupAnswers>, String>
 where
     R: BufRead,
 {
-    let group_strings: Vec<Vec<String>> = reader
-        .lines()
-        .map(|l| l.unwrap())
-        .collect::<Vec<String>>()
-        .join("\n")
-        .split("\n\n")
-        .map(|lines| lines.split('\n').map(|l| l.to_string()).collect())
-        .collect();
+    let mut groups: Vec<GroupAnswers> = vec![];
+    let mut member_answers: Vec<Answers> = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.unwrap();
 
-    group_strings
-        .iter()
-        .map(|lines| GroupAnswers::from_lines(lines))
-        .collect::<Vec<GroupAnswers>>()
+        if line.is_empty() {
+            if !member_answers.is_empty() {
+                groups.push(GroupAnswers { member_answers });
+                member_answers = vec![];
+            }
+            continue;
+        }
+
+        member_answers.push(
+            alphabet
+                .parse_answers(&line)
+                .map_err(|msg| format!("Line {}: {}", i + 1, msg))?,
+        );
+    }
+
+    if !member_answers.is_empty() {
+        groups.push(GroupAnswers { member_answers });
+    }
+
+    Ok(groups)
 }
 
 fn sum_num_questions_any_anwered_yes(groups: &[GroupAnswers]) -> i32 {
@@ -90,3 +447,180 @@ fn sum_num_questions_all_anwered_yes(groups: &[GroupAnswers]) -> i32 {
         .map(GroupAnswers::num_questions_all_anwered_yes)
         .sum()
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use self::test::Bencher;
+    use super::*;
+
+    fn example_group() -> GroupAnswers {
+        read_input("abc\nab\nad\n".as_bytes(), &Alphabet::default())
+            .unwrap()
+            .pop()
+            .unwrap()
+    }
+
+    #[test]
+    fn read_input_example() {
+        let input = "abc\n\na\nb\nc\n\nab\nac\n\na\na\na\na\n\nb\n";
+        let groups = read_input(input.as_bytes(), &Alphabet::default()).unwrap();
+
+        assert_eq!(5, groups.len());
+        assert_eq!(11, sum_num_questions_any_anwered_yes(&groups));
+        assert_eq!(6, sum_num_questions_all_anwered_yes(&groups));
+    }
+
+    #[test]
+    fn read_input_rejects_unknown_questions() {
+        let input = "abc\n\nab\na c\n";
+
+        assert_eq!(
+            Err(
+                "Line 4: Question ' ' is not in the alphabet \"abcdefghijklmnopqrstuvwxyz\""
+                    .to_string()
+            ),
+            read_input(input.as_bytes(), &Alphabet::default()).map(|g| g.len())
+        );
+    }
+
+    #[test]
+    fn custom_alphabet() {
+        let alphabet = Alphabet::new("xyz?").unwrap();
+        let groups = read_input("x?\n?z\n".as_bytes(), &alphabet).unwrap();
+
+        assert_eq!(3, sum_num_questions_any_anwered_yes(&groups));
+        assert_eq!(1, sum_num_questions_all_anwered_yes(&groups));
+        assert!(read_input("a\n".as_bytes(), &alphabet).is_err());
+
+        assert!(Alphabet::new("").is_err());
+        assert!(Alphabet::new("abca").is_err());
+        assert!(Alphabet::new(&"a".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn group_answer_counts() {
+        let group = example_group();
+
+        let expected: BTreeMap<char, usize> = [('a', 3), ('b', 2), ('c', 1), ('d', 1)]
+            .iter()
+            .cloned()
+            .collect();
+        assert_eq!(expected, group.answer_counts(&Alphabet::default()));
+
+        let expected: BTreeMap<usize, usize> = [(1, 2), (2, 1), (3, 1)].iter().cloned().collect();
+        assert_eq!(expected, group.member_histogram());
+        assert_eq!("1=2 2=1 3=1", format_counts(&expected));
+    }
+
+    #[test]
+    fn group_quorum() {
+        let group = example_group();
+
+        assert_eq!(
+            group.num_questions_any_anwered_yes() as usize,
+            group.num_questions_answered_by_at_least(1)
+        );
+        assert_eq!(
+            group.num_questions_all_anwered_yes() as usize,
+            group.num_questions_answered_by_at_least(3)
+        );
+        assert_eq!(2, group.num_questions_answered_by_at_least(2));
+        assert_eq!(0, group.num_questions_answered_by_at_least(4));
+
+        assert_eq!(2, group.num_questions_answered_by_exactly(1));
+        assert_eq!(1, group.num_questions_answered_by_exactly(2));
+        assert_eq!(0, group.num_questions_answered_by_exactly(0));
+    }
+
+    #[test]
+    fn jaccard_similarity_of_answers() {
+        assert_eq!(1.0, jaccard_similarity(0, 0));
+        assert_eq!(1.0, jaccard_similarity(0b101, 0b101));
+        assert_eq!(0.0, jaccard_similarity(0b100, 0b011));
+        assert_eq!(0.5, jaccard_similarity(0b110, 0b010));
+    }
+
+    #[test]
+    fn similarity_report() {
+        let input = "abc\n\nab\nc\n\nxyz\n\nab\n\nxy\n";
+        let groups = read_input(input.as_bytes(), &Alphabet::default()).unwrap();
+
+        let unions: Vec<Answers> = groups.iter().map(GroupAnswers::union).collect();
+        assert_eq!(
+            vec![vec![0, 1, 3], vec![2, 4]],
+            cluster_groups(&unions, 0.6)
+        );
+        assert_eq!(vec![vec![0, 1]], cluster_groups(&unions, 1.0));
+
+        assert_eq!(
+            "kind,group_a,group_b,similarity,cluster\n\
+             most_similar,1,2,1.0000,\n\
+             most_similar,1,4,0.6667,\n\
+             most_dissimilar,4,5,0.0000,\n\
+             most_dissimilar,3,4,0.0000,\n\
+             cluster,1,,,1\n\
+             cluster,2,,,1\n\
+             cluster,4,,,1\n\
+             cluster,3,,,2\n\
+             cluster,5,,,2\n",
+            similarity_csv(&groups, 0.6, 2)
+        );
+    }
+
+    #[test]
+    fn top_pairs_do_not_overlap() {
+        let unions: Vec<Answers> = vec![0b111, 0b111, 0b111000, 0b11, 0b11000];
+
+        let (most_similar, most_dissimilar) = top_pairs(&unions, 6);
+        assert_eq!(6, most_similar.len());
+        assert_eq!((0, 1, 1.0), most_similar[0]);
+        assert_eq!(4, most_dissimilar.len());
+        assert_eq!((3, 4, 0.0), most_dissimilar[0]);
+        for pair in most_dissimilar.iter() {
+            assert!(!most_similar.contains(pair));
+        }
+
+        let (most_similar, most_dissimilar) = top_pairs(&unions[..1], 3);
+        assert!(most_similar.is_empty());
+        assert!(most_dissimilar.is_empty());
+    }
+
+    /// Generates a survey where each group has between one and five members.
+    fn generate_survey(num_groups: usize) -> String {
+        let questions: Vec<char> = "abcdefghijklmnopqrstuvwxyz".chars().collect();
+
+        let mut input = String::new();
+        for i in 0..num_groups {
+            for member in 0..(i % 5 + 1) {
+                let answers: String = questions
+                    .iter()
+                    .enumerate()
+                    .filter(|(q, _)| (q * 7 + i + member) % 3 != 0)
+                    .map(|(_, c)| *c)
+                    .collect();
+                input.push_str(&answers);
+                input.push('\n');
+            }
+            input.push('\n');
+        }
+
+        input
+    }
+
+    #[bench]
+    fn bench_read_input_and_sum(b: &mut Bencher) {
+        let alphabet = Alphabet::default();
+        let input = generate_survey(10_000);
+
+        b.bytes = input.len() as u64;
+        b.iter(|| {
+            let groups = read_input(input.as_bytes(), &alphabet).unwrap();
+            (
+                sum_num_questions_any_anwered_yes(&groups),
+                sum_num_questions_all_anwered_yes(&groups),
+            )
+        });
+    }
+}
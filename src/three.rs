@@ -1,24 +1,27 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
 use std::io;
 use std::io::prelude::*;
+use std::str::FromStr;
 
 pub fn part_one() {
-    let grid = read_input();
-    let answer = count_trees_on_path(
+    let grid = read_input(TerrainLegend::default()).unwrap();
+    let answer = get_path_cost(
         &grid,
         &PositionChange {
             x_shift: 3,
             y_shift: 1,
         },
         &Position { x: 0, y: 0 },
-        0,
     );
 
     println!("{}", answer);
 }
 
 pub fn part_two() {
-    let grid = read_input();
-    let answer = count_and_multiply_trees_on_paths(
+    let grid = read_input(TerrainLegend::default()).unwrap();
+    let answer = multiply_path_costs(
         &grid,
         &[
             PositionChange {
@@ -48,62 +51,420 @@ pub fn part_two() {
     println!("{}", answer);
 }
 
-struct Position {
-    x: usize,
-    y: usize,
+/// Walks the given path on the map and prints the total cost of the path, the number of cells of
+/// each terrain type hit, and the visited positions. If a render format is given, the map is
+/// printed with the path marked on it instead.
+pub fn walk(
+    slope: &PositionChange,
+    options: &PathOptions,
+    legend_path: Option<&str>,
+    render: Option<RenderFormat>,
+) {
+    let path_and_grid = read_legend(legend_path)
+        .and_then(read_input)
+        .and_then(|grid| walk_path(&grid, slope, options).map(|path| (path, grid)));
+    let (path, grid) = match path_and_grid {
+        Ok(path_and_grid) => path_and_grid,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+
+    match render {
+        None => {
+            println!("cost: {}", path.total_cost);
+            for (terrain, count) in grid.legend.terrains.iter().zip(path.terrain_counts.iter()) {
+                println!("{}: {}", terrain.name, count);
+            }
+            for position in path.visited.iter() {
+                println!("{},{}", position.x, position.y);
+            }
+        }
+        Some(RenderFormat::Text) => print!("{}", render_text(&grid, slope, &path)),
+        Some(RenderFormat::Ppm) => io::stdout()
+            .write_all(&render_ppm(&grid, slope, &path))
+            .unwrap(),
+    }
+}
+
+/// Evaluates every slope within the given inclusive bounds from the top left corner of the map and
+/// prints the slopes with the lowest and the highest total cost.
+pub fn search(x_shift_bounds: (i32, i32), y_shift_bounds: (i32, i32), legend_path: Option<&str>) {
+    let result = read_legend(legend_path)
+        .and_then(read_input)
+        .and_then(|grid| search_slopes(&grid, x_shift_bounds, y_shift_bounds));
+    let result = match result {
+        Ok(result) => result,
+        Err(msg) => {
+            println!("{}", msg);
+            return;
+        }
+    };
+
+    let format_slopes = |slopes: &[PositionChange]| {
+        slopes
+            .iter()
+            .map(|s| format!("{},{}", s.x_shift, s.y_shift))
+            .collect::<Vec<String>>()
+            .join(" ")
+    };
+
+    println!(
+        "lowest: {} with slopes {}",
+        result.lowest_cost,
+        format_slopes(&result.lowest_slopes)
+    );
+    println!(
+        "highest: {} with slopes {}",
+        result.highest_cost,
+        format_slopes(&result.highest_slopes)
+    );
 }
 
-impl Position {
-    pub fn shifted(&self, change: &PositionChange) -> Position {
-        let x_shift = change.x_shift;
-        let y_shift = change.y_shift;
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderFormat {
+    Text,
+    Ppm,
+}
+
+impl FromStr for RenderFormat {
+    type Err = String;
+
+    fn from_str(format_str: &str) -> Result<RenderFormat, String> {
+        match format_str {
+            "text" => Ok(RenderFormat::Text),
+            "ppm" => Ok(RenderFormat::Ppm),
+            _ => Err(format!("Unknown render format: {}", format_str)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub x: usize,
+    pub y: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PositionChange {
+    pub x_shift: i32,
+    pub y_shift: i32,
+}
 
-        Position {
-            x: ((self.x as i32) + x_shift) as usize,
-            y: ((self.y as i32) + y_shift) as usize,
+pub struct PathOptions {
+    pub starting_position: Position,
+    /// Whether the path should continue from the top of the grid once it goes past the bottom
+    /// (and vice versa), rather than ending.
+    pub wrap_vertically: bool,
+    /// Maximum number of slope steps to take from the starting position.
+    pub max_steps: Option<usize>,
+}
+
+impl PathOptions {
+    pub fn new(starting_position: Position) -> PathOptions {
+        PathOptions {
+            starting_position,
+            wrap_vertically: false,
+            max_steps: None,
         }
     }
 }
 
-struct PositionChange {
-    x_shift: i32,
-    y_shift: i32,
+struct Path {
+    /// Positions visited by the path, in order, starting with the starting position. The x
+    /// coordinates are within the width of the grid.
+    visited: Vec<Position>,
+    total_cost: i64,
+    /// Number of visited cells of each terrain type, indexed the same as the terrain legend.
+    terrain_counts: Vec<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Terrain {
+    symbol: char,
+    name: String,
+    cost: i64,
+}
+
+/// The terrain types that can appear in a grid, along with the character used for each one and
+/// the cost of passing through it.
+#[derive(Clone, Debug, PartialEq)]
+struct TerrainLegend {
+    terrains: Vec<Terrain>,
+}
+
+impl TerrainLegend {
+    /// Returns the legend used by the puzzle, where each tree has a cost of one.
+    fn default() -> TerrainLegend {
+        TerrainLegend {
+            terrains: vec![
+                Terrain {
+                    symbol: '.',
+                    name: "open".to_string(),
+                    cost: 0,
+                },
+                Terrain {
+                    symbol: '#',
+                    name: "tree".to_string(),
+                    cost: 1,
+                },
+            ],
+        }
+    }
+
+    /// Parses a legend with one terrain type per line, given as the symbol, name, and cost
+    /// separated by spaces (ex. "# tree 1"). Blank lines are ignored.
+    fn from_str(legend_str: &str) -> Result<TerrainLegend, String> {
+        let mut terrains: Vec<Terrain> = vec![];
+        for (i, line) in legend_str.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() != 3 || parts[0].chars().count() != 1 {
+                return Err(format!("Invalid legend line {}: {}", i + 1, line));
+            }
+
+            let symbol = parts[0].chars().next().unwrap();
+            let name = parts[1].to_string();
+            let cost = match parts[2].parse::<i64>() {
+                Ok(cost) => cost,
+                Err(_) => return Err(format!("Invalid terrain cost on line {}: {}", i + 1, line)),
+            };
+
+            if terrains.iter().any(|t| t.symbol == symbol) {
+                return Err(format!(
+                    "Duplicate terrain symbol on line {}: {}",
+                    i + 1,
+                    symbol
+                ));
+            }
+
+            terrains.push(Terrain { symbol, name, cost });
+        }
+
+        if terrains.is_empty() {
+            return Err("Legend does not contain any terrain types".to_string());
+        }
+
+        Ok(TerrainLegend { terrains })
+    }
+
+    fn get_index(&self, symbol: char) -> Option<usize> {
+        self.terrains.iter().position(|t| t.symbol == symbol)
+    }
 }
 
 struct Grid {
-    /// Grid of cells indicating whether a give cell in the grid contains a tree or not
-    /// (True=tree, False=no tree). First dimension is rows where 0 is top, second dimension is
-    /// columns where 0 is left side.
-    cells: Vec<Vec<bool>>,
+    legend: TerrainLegend,
+    /// Grid of cells indicating the terrain type of each cell in the grid, as an index into the
+    /// legend. First dimension is rows where 0 is top, second dimension is columns where 0 is left
+    /// side.
+    cells: Vec<Vec<usize>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct OverlayCell {
+    terrain: usize,
+    visited: bool,
+}
+
+/// Bitsets for each row of a grid indicating which cells contain each terrain type that has a
+/// cost, used to quickly evaluate many slopes on the same grid.
+struct TerrainBitsets {
+    width: usize,
+    /// Cost of each terrain type along with its bitset for each row.
+    terrains: Vec<(i64, Vec<Vec<u64>>)>,
+}
+
+impl TerrainBitsets {
+    fn from_grid(grid: &Grid) -> TerrainBitsets {
+        let width = grid.width();
+        let terrains = grid
+            .legend
+            .terrains
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.cost != 0)
+            .map(|(i, t)| {
+                let rows = grid
+                    .cells
+                    .iter()
+                    .map(|row| {
+                        let mut bits = vec![0u64; width.div_ceil(64)];
+                        for (x, terrain) in row.iter().enumerate() {
+                            if *terrain == i {
+                                bits[x / 64] |= 1 << (x % 64);
+                            }
+                        }
+
+                        bits
+                    })
+                    .collect();
+
+                (t.cost, rows)
+            })
+            .collect();
+
+        TerrainBitsets { width, terrains }
+    }
+
+    /// Gets the total cost of going from the top left corner to the bottom of the grid for each of
+    /// the given x shifts, which must already be wrapped to within the width of the grid. Each row
+    /// on the path is only visited once for all of the x shifts: at every row, the x shifts that
+    /// land on each terrain are collected into a bitset, and the cost of the terrain is then added
+    /// to each of those x shifts.
+    fn get_path_costs(&self, x_shifts: &[usize], y_shift: usize) -> Vec<i64> {
+        let height = self.terrains.first().map_or(0, |(_, rows)| rows.len());
+
+        let mut costs = vec![0; x_shifts.len()];
+        let mut hits = vec![0u64; x_shifts.len().div_ceil(64)];
+        for (step, y) in (0..height).step_by(y_shift).enumerate() {
+            for (cost, rows) in self.terrains.iter() {
+                let row = &rows[y];
+                if row.iter().all(|bits| *bits == 0) {
+                    continue;
+                }
+
+                hits.iter_mut().for_each(|h| *h = 0);
+                for (i, x_shift) in x_shifts.iter().enumerate() {
+                    let x = step * x_shift % self.width;
+                    hits[i / 64] |= ((row[x / 64] >> (x % 64)) & 1) << (i % 64);
+                }
+
+                for (word_index, word) in hits.iter().enumerate() {
+                    let mut remaining = *word;
+                    while remaining != 0 {
+                        costs[word_index * 64 + remaining.trailing_zeros() as usize] += cost;
+                        remaining &= remaining - 1;
+                    }
+                }
+            }
+        }
+
+        costs
+    }
+}
+
+struct SlopeSearchResult {
+    lowest_cost: i64,
+    lowest_slopes: Vec<PositionChange>,
+    highest_cost: i64,
+    highest_slopes: Vec<PositionChange>,
 }
 
 impl Grid {
-    pub fn from_str(grid_str: &str) -> Grid {
-        let mut cells: Vec<Vec<bool>> = vec![];
-        for line in grid_str.split('\n') {
-            let mut row: Vec<bool> = vec![];
-            for c in line.chars() {
-                let value = match c {
-                    '.' => false,
-                    '#' => true,
-                    _ => panic!("Invalid grid character: {}", c),
-                };
+    pub fn from_str(grid_str: &str, legend: TerrainLegend) -> Result<Grid, String> {
+        let mut cells: Vec<Vec<usize>> = vec![];
+        for (y, line) in grid_str.split('\n').enumerate() {
+            let mut row: Vec<usize> = vec![];
+            for (x, c) in line.chars().enumerate() {
+                match legend.get_index(c) {
+                    Some(terrain) => row.push(terrain),
+                    None => return Err(format!("Invalid grid character at ({}, {}): {}", x, y, c)),
+                }
+            }
 
-                row.push(value);
+            if let Some(first_row) = cells.first() {
+                if row.len() != first_row.len() {
+                    return Err(format!(
+                        "Grid row {} has width {}, but the first row has width {}",
+                        y,
+                        row.len(),
+                        first_row.len()
+                    ));
+                }
             }
 
             cells.push(row);
         }
 
-        Grid { cells }
+        if cells[0].is_empty() {
+            return Err("Grid is empty".to_string());
+        }
+
+        Ok(Grid { legend, cells })
     }
 
-    pub fn get(&self, position: &Position) -> bool {
+    pub fn get(&self, position: &Position) -> &Terrain {
         assert!(position.y < self.height());
 
         let x_adjusted = position.x % self.width();
 
-        self.cells[position.y][x_adjusted]
+        &self.legend.terrains[self.cells[position.y][x_adjusted]]
+    }
+
+    /// Returns the position reached by applying the given change to the given position, or None
+    /// if the change would move off of the top or bottom of the grid. Horizontal movement always
+    /// wraps around.
+    pub fn shifted(
+        &self,
+        position: &Position,
+        change: &PositionChange,
+        wrap_vertically: bool,
+    ) -> Option<Position> {
+        let width = self.width() as i64;
+        let height = self.height() as i64;
+
+        let x = (position.x as i64 + change.x_shift as i64).rem_euclid(width);
+        let y = position.y as i64 + change.y_shift as i64;
+
+        let y = if wrap_vertically {
+            y.rem_euclid(height)
+        } else if y < 0 || y >= height {
+            return None;
+        } else {
+            y
+        };
+
+        Some(Position {
+            x: x as usize,
+            y: y as usize,
+        })
+    }
+
+    /// Returns the cells of the grid with the given path marked on them. The grid is repeated
+    /// horizontally as many times as needed to show how far the path travels to either side.
+    fn overlay_path(&self, slope: &PositionChange, path: &Path) -> Vec<Vec<OverlayCell>> {
+        let width = self.width() as i64;
+
+        // The path only keeps wrapped x coordinates, so recover how far it actually travelled
+        let start_x = path.visited.first().map_or(0, |p| p.x as i64);
+        let unwrapped_xs: Vec<i64> = (0..path.visited.len() as i64)
+            .map(|step| start_x + step * slope.x_shift as i64)
+            .collect();
+
+        let min_tile = unwrapped_xs
+            .iter()
+            .min()
+            .map_or(0, |x| x.div_euclid(width).min(0));
+        let max_tile = unwrapped_xs
+            .iter()
+            .max()
+            .map_or(0, |x| x.div_euclid(width).max(0));
+        let left = min_tile * width;
+        let right = (max_tile + 1) * width;
+
+        let mut overlay: Vec<Vec<OverlayCell>> = self
+            .cells
+            .iter()
+            .map(|row| {
+                (left..right)
+                    .map(|x| OverlayCell {
+                        terrain: row[x.rem_euclid(width) as usize],
+                        visited: false,
+                    })
+                    .collect()
+            })
+            .collect();
+
+        for (position, x) in path.visited.iter().zip(unwrapped_xs.iter()) {
+            overlay[position.y][(x - left) as usize].visited = true;
+        }
+
+        overlay
     }
 
     pub fn height(&self) -> usize {
@@ -117,7 +478,30 @@ impl Grid {
     }
 }
 
-fn read_input() -> Grid {
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in self.cells.iter() {
+            for terrain in row.iter() {
+                write!(f, "{}", self.legend.terrains[*terrain].symbol)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn read_legend(legend_path: Option<&str>) -> Result<TerrainLegend, String> {
+    match legend_path {
+        None => Ok(TerrainLegend::default()),
+        Some(path) => match fs::read_to_string(path) {
+            Ok(legend_str) => TerrainLegend::from_str(&legend_str),
+            Err(err) => Err(format!("Unable to read legend file {}: {}", path, err)),
+        },
+    }
+}
+
+fn read_input(legend: TerrainLegend) -> Result<Grid, String> {
     let grid_string = io::stdin()
         .lock()
         .lines()
@@ -125,40 +509,436 @@ fn read_input() -> Grid {
         .collect::<Vec<String>>()
         .join("\n");
 
-    Grid::from_str(&grid_string)
+    Grid::from_str(&grid_string, legend)
 }
 
-fn count_trees_on_path(
-    grid: &Grid,
-    slope: &PositionChange,
-    position: &Position,
-    trees_hit: i32,
-) -> i32 {
-    if position.y >= grid.height() {
-        trees_hit
-    } else {
-        let trees_hit_inc = if grid.get(&position) { 1 } else { 0 };
-
-        count_trees_on_path(
-            grid,
-            slope,
-            &position.shifted(slope),
-            trees_hit + trees_hit_inc,
-        )
+/// Walks the grid from the starting position using the given slope until the path leaves the grid,
+/// returns to the starting position, or reaches the maximum number of steps.
+fn walk_path(grid: &Grid, slope: &PositionChange, options: &PathOptions) -> Result<Path, String> {
+    let start = options.starting_position;
+    if start.y >= grid.height() {
+        return Err(format!(
+            "Starting position is outside of the grid: ({}, {})",
+            start.x, start.y
+        ));
+    }
+
+    let start = Position {
+        x: start.x % grid.width(),
+        y: start.y,
+    };
+
+    let mut visited: Vec<Position> = vec![];
+    let mut total_cost = 0;
+    let mut terrain_counts = vec![0; grid.legend.terrains.len()];
+
+    let mut position = start;
+    loop {
+        total_cost += grid.get(&position).cost;
+        terrain_counts[grid.cells[position.y][position.x]] += 1;
+        visited.push(position);
+
+        if let Some(max_steps) = options.max_steps {
+            if visited.len() > max_steps {
+                break;
+            }
+        }
+
+        match grid.shifted(&position, slope, options.wrap_vertically) {
+            Some(next) if next != start => position = next,
+            _ => break,
+        }
     }
+
+    Ok(Path {
+        visited,
+        total_cost,
+        terrain_counts,
+    })
 }
 
-fn count_and_multiply_trees_on_paths(
+fn get_path_cost(grid: &Grid, slope: &PositionChange, position: &Position) -> i64 {
+    walk_path(grid, slope, &PathOptions::new(*position))
+        .unwrap()
+        .total_cost
+}
+
+fn multiply_path_costs(
     grid: &Grid,
     slopes: &[PositionChange],
     starting_position: &Position,
 ) -> i64 {
-    let mut nums_of_trees_hit: Vec<i64> = vec![];
-    for slope in slopes.iter() {
-        let trees_hit = count_trees_on_path(grid, slope, starting_position, 0);
+    slopes
+        .iter()
+        .map(|slope| get_path_cost(grid, slope, starting_position))
+        .product()
+}
 
-        nums_of_trees_hit.push(trees_hit as i64);
+/// Renders the map with the path marked on it, using "O" for cells without a cost and "X" for
+/// cells with a cost (ex. trees) that the path hits.
+fn render_text(grid: &Grid, slope: &PositionChange, path: &Path) -> String {
+    let mut rendered = String::new();
+    for row in grid.overlay_path(slope, path) {
+        rendered.extend(row.iter().map(|c| {
+            let terrain = &grid.legend.terrains[c.terrain];
+            match (c.visited, terrain.cost != 0) {
+                (false, _) => terrain.symbol,
+                (true, false) => 'O',
+                (true, true) => 'X',
+            }
+        }));
+        rendered.push('\n');
+    }
+
+    rendered
+}
+
+/// Renders the map with the path marked on it as a binary PPM image with one pixel per cell.
+fn render_ppm(grid: &Grid, slope: &PositionChange, path: &Path) -> Vec<u8> {
+    let overlay = grid.overlay_path(slope, path);
+    let height = overlay.len();
+    let width = overlay.first().map_or(0, |row| row.len());
+
+    let mut image = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    for row in overlay.iter() {
+        for cell in row.iter() {
+            let has_cost = grid.legend.terrains[cell.terrain].cost != 0;
+            let rgb = match (cell.visited, has_cost) {
+                (false, false) => [255, 255, 255],
+                (false, true) => [34, 139, 34],
+                (true, false) => [65, 105, 225],
+                (true, true) => [220, 20, 60],
+            };
+
+            image.extend_from_slice(&rgb);
+        }
+    }
+
+    image
+}
+
+/// Finds the slopes within the given inclusive bounds with the lowest and the highest total cost
+/// when starting from the top left corner. Slopes that wrap to the same horizontal shift share a
+/// result, and all of the horizontal shifts for each vertical shift are evaluated in one pass over
+/// the rows.
+fn search_slopes(
+    grid: &Grid,
+    x_shift_bounds: (i32, i32),
+    y_shift_bounds: (i32, i32),
+) -> Result<SlopeSearchResult, String> {
+    let (min_x_shift, max_x_shift) = x_shift_bounds;
+    let (min_y_shift, max_y_shift) = y_shift_bounds;
+
+    if min_y_shift < 1 {
+        return Err(format!(
+            "Slope y shifts must be at least 1: {}",
+            min_y_shift
+        ));
+    }
+    if min_x_shift > max_x_shift || min_y_shift > max_y_shift {
+        return Err("Slope bounds must have the minimum before the maximum".to_string());
+    }
+
+    if grid.height() == 0 || grid.width() == 0 {
+        return Err("Cannot search slopes on an empty grid".to_string());
+    }
+
+    let bitsets = TerrainBitsets::from_grid(grid);
+    let width = grid.width() as i32;
+
+    let wrapped_x_shifts: Vec<usize> = (min_x_shift..=max_x_shift)
+        .map(|x_shift| x_shift.rem_euclid(width) as usize)
+        .collect::<BTreeSet<usize>>()
+        .into_iter()
+        .collect();
+
+    let mut result: Option<SlopeSearchResult> = None;
+    for y_shift in min_y_shift..=max_y_shift {
+        let costs = bitsets.get_path_costs(&wrapped_x_shifts, y_shift as usize);
+
+        for x_shift in min_x_shift..=max_x_shift {
+            let wrapped_x_shift = x_shift.rem_euclid(width) as usize;
+            let cost = costs[wrapped_x_shifts.binary_search(&wrapped_x_shift).unwrap()];
+
+            let slope = PositionChange { x_shift, y_shift };
+            match result.as_mut() {
+                None => {
+                    result = Some(SlopeSearchResult {
+                        lowest_cost: cost,
+                        lowest_slopes: vec![slope],
+                        highest_cost: cost,
+                        highest_slopes: vec![slope],
+                    })
+                }
+                Some(r) => {
+                    if cost < r.lowest_cost {
+                        r.lowest_cost = cost;
+                        r.lowest_slopes = vec![slope];
+                    } else if cost == r.lowest_cost {
+                        r.lowest_slopes.push(slope);
+                    }
+
+                    if cost > r.highest_cost {
+                        r.highest_cost = cost;
+                        r.highest_slopes = vec![slope];
+                    } else if cost == r.highest_cost {
+                        r.highest_slopes.push(slope);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(result.unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(grid_str: &str) -> Grid {
+        Grid::from_str(grid_str, TerrainLegend::default()).unwrap()
+    }
+
+    fn example_grid() -> Grid {
+        grid(
+            "..##.......\n#...#...#..\n.#....#..#.\n..#.#...#.#\n.#...##..#.\n..#.##.....\n.#.#.#....#\n.#........#\n#.##...#...\n#...##....#\n.#..#...#.#",
+        )
+    }
+
+    #[test]
+    fn get_path_cost_example() {
+        let grid = example_grid();
+        let slope = PositionChange {
+            x_shift: 3,
+            y_shift: 1,
+        };
+
+        let expected = 7;
+        let actual = get_path_cost(&grid, &slope, &Position { x: 0, y: 0 });
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn walk_path_leftward_slope() {
+        let grid = example_grid();
+        let slope = PositionChange {
+            x_shift: -3,
+            y_shift: 1,
+        };
+
+        let path = walk_path(&grid, &slope, &PathOptions::new(Position { x: 0, y: 0 })).unwrap();
+
+        assert_eq!(11, path.visited.len());
+        assert_eq!(Position { x: 8, y: 1 }, path.visited[1]);
+        assert_eq!(Position { x: 5, y: 2 }, path.visited[2]);
+    }
+
+    #[test]
+    fn walk_path_upward_slope_wrap_vertically() {
+        let grid = example_grid();
+        let slope = PositionChange {
+            x_shift: 0,
+            y_shift: -1,
+        };
+        let mut options = PathOptions::new(Position { x: 2, y: 3 });
+        options.wrap_vertically = true;
+
+        let path = walk_path(&grid, &slope, &options).unwrap();
+
+        assert_eq!(11, path.visited.len());
+        assert_eq!(Position { x: 2, y: 10 }, path.visited[4]);
+        assert_eq!(4, path.total_cost);
     }
 
-    nums_of_trees_hit.iter().product()
+    #[test]
+    fn walk_path_max_steps() {
+        let grid = example_grid();
+        let slope = PositionChange {
+            x_shift: 1,
+            y_shift: 1,
+        };
+        let mut options = PathOptions::new(Position { x: 0, y: 0 });
+        options.max_steps = Some(2);
+
+        let path = walk_path(&grid, &slope, &options).unwrap();
+
+        assert_eq!(3, path.visited.len());
+    }
+
+    #[test]
+    fn walk_path_start_outside_grid() {
+        let grid = example_grid();
+        let slope = PositionChange {
+            x_shift: 1,
+            y_shift: 1,
+        };
+
+        let result = walk_path(&grid, &slope, &PathOptions::new(Position { x: 0, y: 11 }));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn search_slopes_matches_walked_paths() {
+        let grid = example_grid();
+
+        let result = search_slopes(&grid, (-4, 7), (1, 2)).unwrap();
+
+        let start = Position { x: 0, y: 0 };
+        for slope in result.lowest_slopes.iter() {
+            assert_eq!(result.lowest_cost, get_path_cost(&grid, slope, &start));
+        }
+        for slope in result.highest_slopes.iter() {
+            assert_eq!(result.highest_cost, get_path_cost(&grid, slope, &start));
+        }
+
+        assert_eq!(7, result.highest_cost);
+        assert!(result.highest_slopes.contains(&PositionChange {
+            x_shift: 3,
+            y_shift: 1
+        }));
+    }
+
+    #[test]
+    fn get_path_costs_matches_walked_paths() {
+        let legend = TerrainLegend::from_str("# tree 1\n. open 0\n~ water 5").unwrap();
+        let grid = Grid::from_str("..#~\n#.~.\n.#.#\n~..#\n#~#.", legend).unwrap();
+        let bitsets = TerrainBitsets::from_grid(&grid);
+
+        let x_shifts = [0, 1, 2, 3];
+        for y_shift in 1..=3 {
+            let costs = bitsets.get_path_costs(&x_shifts, y_shift);
+            for (x_shift, cost) in x_shifts.iter().zip(costs.iter()) {
+                let slope = PositionChange {
+                    x_shift: *x_shift as i32,
+                    y_shift: y_shift as i32,
+                };
+
+                assert_eq!(
+                    get_path_cost(&grid, &slope, &Position { x: 0, y: 0 }),
+                    *cost
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn search_slopes_invalid_bounds() {
+        let grid = example_grid();
+
+        assert!(search_slopes(&grid, (0, 3), (0, 2)).is_err());
+        assert!(search_slopes(&grid, (3, 0), (1, 2)).is_err());
+
+        let empty_grid = Grid {
+            legend: TerrainLegend::default(),
+            cells: vec![vec![]],
+        };
+        assert!(search_slopes(&empty_grid, (0, 3), (1, 2)).is_err());
+    }
+
+    #[test]
+    fn grid_display_round_trip() {
+        let grid_str = "..##.......\n#...#...#..\n.#....#..#.\n";
+        let grid = grid(grid_str.trim_end());
+
+        assert_eq!(grid_str, grid.to_string());
+    }
+
+    #[test]
+    fn render_text_repeats_grid() {
+        let grid = grid("..#\n#..\n.#.");
+        let slope = PositionChange {
+            x_shift: 2,
+            y_shift: 1,
+        };
+        let path = walk_path(&grid, &slope, &PathOptions::new(Position { x: 0, y: 0 })).unwrap();
+
+        let expected = "O.#..#\n#.O#..\n.#..X.\n";
+        let actual = render_text(&grid, &slope, &path);
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn render_text_leftward_slope() {
+        let grid = grid("..#\n#..\n.#.");
+        let slope = PositionChange {
+            x_shift: -1,
+            y_shift: 1,
+        };
+        let path = walk_path(&grid, &slope, &PathOptions::new(Position { x: 0, y: 0 })).unwrap();
+
+        let expected = "..#O.#\n#.O#..\n.X..#.\n";
+        let actual = render_text(&grid, &slope, &path);
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn render_ppm_header() {
+        let grid = grid("..#\n#..");
+        let slope = PositionChange {
+            x_shift: 1,
+            y_shift: 1,
+        };
+        let path = walk_path(&grid, &slope, &PathOptions::new(Position { x: 0, y: 0 })).unwrap();
+
+        let image = render_ppm(&grid, &slope, &path);
+        let header = b"P6\n3 2\n255\n";
+
+        assert_eq!(&header[..], &image[..header.len()]);
+        assert_eq!(header.len() + 3 * 2 * 3, image.len());
+    }
+
+    #[test]
+    fn terrain_legend_from_str() {
+        let legend = TerrainLegend::from_str(". open 0\n# tree 1\n\n^ rock 5\n* snow 2\n").unwrap();
+
+        assert_eq!(4, legend.terrains.len());
+        assert_eq!(Some(2), legend.get_index('^'));
+        assert_eq!("snow", legend.terrains[3].name);
+        assert_eq!(2, legend.terrains[3].cost);
+    }
+
+    #[test]
+    fn terrain_legend_from_str_invalid() {
+        assert!(TerrainLegend::from_str("# tree").is_err());
+        assert!(TerrainLegend::from_str("## tree 1").is_err());
+        assert!(TerrainLegend::from_str("# tree one").is_err());
+        assert!(TerrainLegend::from_str("# tree 1\n# rock 2").is_err());
+        assert!(TerrainLegend::from_str("").is_err());
+    }
+
+    #[test]
+    fn grid_from_str_unknown_terrain() {
+        assert!(Grid::from_str("..^", TerrainLegend::default()).is_err());
+    }
+
+    #[test]
+    fn grid_from_str_invalid_size() {
+        assert!(Grid::from_str("..#\n.", TerrainLegend::default()).is_err());
+        assert!(Grid::from_str("", TerrainLegend::default()).is_err());
+        assert!(Grid::from_str("\n", TerrainLegend::default()).is_err());
+    }
+
+    #[test]
+    fn walk_path_weighted_terrain() {
+        let legend = TerrainLegend::from_str(". open 0\n# tree 1\n^ rock 5\n* snow 2").unwrap();
+        let grid = Grid::from_str("^..\n.#.\n..*\n^^^", legend).unwrap();
+        let slope = PositionChange {
+            x_shift: 1,
+            y_shift: 1,
+        };
+
+        let path = walk_path(&grid, &slope, &PathOptions::new(Position { x: 0, y: 0 })).unwrap();
+
+        assert_eq!(5 + 1 + 2 + 5, path.total_cost);
+        assert_eq!(vec![0, 1, 2, 1], path.terrain_counts);
+
+        let search = search_slopes(&grid, (1, 1), (1, 1)).unwrap();
+
+        assert_eq!(path.total_cost, search.lowest_cost);
+    }
 }